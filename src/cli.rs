@@ -21,6 +21,8 @@ use std::path::PathBuf;
 pub use clap::Parser;
 use clap::Subcommand;
 
+use crate::{scan::Exclude, support_files::SupportFiles};
+
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
@@ -47,7 +49,43 @@ pub enum Commands {
     Scan {
         mame_xml: PathBuf,
         genre_ini: PathBuf,
-        roms_dir: PathBuf
+        #[command(flatten)]
+        support_files: SupportFiles,
+        #[arg(required = true)]
+        roms_dirs: Vec<PathBuf>,
+        /// Search sub-directories of rom dirs for rom sets
+        #[arg(long, short)]
+        recursive: bool,
+        /// Rom sets are merged, clones missing from rom dirs are launched from the parent set
+        #[arg(long)]
+        merged: bool,
+        /// Name of emulator in config used to launch roms [default: mame]
+        #[arg(long)]
+        emulator: Option<String>,
+        /// Kind of machine to leave out, in addition to `scan.exclude` from config
+        #[arg(long, value_enum)]
+        exclude: Vec<Exclude>
+    },
+
+    /// Add roms of a non-mame system to database by scanning for file extensions
+    ScanDir {
+        /// Name of emulator in config used to launch roms
+        system: String,
+        roms_dir: PathBuf,
+        /// File extension of roms [default: emulator extensions from config]
+        #[arg(long = "ext")]
+        extensions: Vec<String>,
+        /// Logiqx XML DAT file (e.g. No-Intro) with titles and metadata
+        #[arg(long)]
+        dat: Option<PathBuf>
+    },
+
+    /// Set extra mame arguments for a rom in the database
+    Params {
+        rom: String,
+        /// Arguments passed to mame (e.g. "-rotate"), omit to clear
+        #[arg(allow_hyphen_values = true)]
+        params: Option<String>
     },
 
     /// Make keymap interactively and write to file
@@ -0,0 +1,150 @@
+/*
+ * Lemon Launcher - SDL based MAME frontend for arcade cabinets
+ * Copyright (C) 2024 Josh Kropf <josh@slashdev.ca>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{collections::HashMap, fs::File, io::BufReader, path::Path};
+
+use anyhow::Result;
+use quick_xml::de::from_reader;
+use serde::Deserialize;
+
+/// Logiqx style XML DAT file as used by No-Intro, Redump and MAME subsets
+#[derive(Deserialize)]
+pub struct Datafile {
+    #[serde(rename="game", alias="machine", default = "Vec::new")]
+    pub games: Vec<Game>
+}
+
+impl Datafile {
+    pub fn load_xml(file_path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(file_path)?;
+        let reader = BufReader::new(file);
+
+        Ok(from_reader(reader)?)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Game {
+    #[serde(rename="@name")]
+    pub name: String,
+
+    #[serde(rename="@cloneof")]
+    pub clone_of: Option<String>,
+
+    pub description: String,
+
+    pub year: Option<String>,
+
+    #[serde(alias="publisher")]
+    pub manufacturer: Option<String>,
+
+    #[serde(rename="rom", default = "Vec::new")]
+    pub roms: Vec<GameRom>
+}
+
+#[derive(Deserialize)]
+pub struct GameRom {
+    #[serde(rename="@name")]
+    pub name: String,
+
+    #[serde(rename="@crc")]
+    pub crc: Option<String>
+}
+
+/// Lookup of DAT games by file name, game name or CRC of rom file
+pub struct DatIndex {
+    games: Vec<Game>,
+    by_name: HashMap<String, usize>,
+    by_crc: HashMap<u32, usize>
+}
+
+impl DatIndex {
+    pub fn load_xml(file_path: impl AsRef<Path>) -> Result<Self> {
+        let dat = Datafile::load_xml(file_path)?;
+        Ok(Self::from(dat))
+    }
+
+    /// Find game by rom file name (e.g. "Tetris (World).gb") or the file name
+    /// without extension matching the game name
+    pub fn find_by_name(&self, file_name: &str, file_stem: &str) -> Option<&Game> {
+        self.by_name.get(&file_name.to_lowercase())
+            .or_else(|| self.by_name.get(&file_stem.to_lowercase()))
+            .map(|i| &self.games[*i])
+    }
+
+    pub fn find_by_crc(&self, crc: u32) -> Option<&Game> {
+        self.by_crc.get(&crc)
+            .map(|i| &self.games[*i])
+    }
+}
+
+impl From<Datafile> for DatIndex {
+    fn from(dat: Datafile) -> Self {
+        let mut by_name = HashMap::new();
+        let mut by_crc = HashMap::new();
+
+        for (index, game) in dat.games.iter().enumerate() {
+            by_name.insert(game.name.to_lowercase(), index);
+
+            for rom in &game.roms {
+                by_name.entry(rom.name.to_lowercase()).or_insert(index);
+
+                if let Some(crc) = rom.crc.as_ref().and_then(|c| u32::from_str_radix(c, 16).ok()) {
+                    by_crc.entry(crc).or_insert(index);
+                }
+            }
+        }
+
+        DatIndex { games: dat.games, by_name, by_crc }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dat_index() -> DatIndex {
+        let dat_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/nointro.dat");
+        DatIndex::load_xml(dat_path).unwrap()
+    }
+
+    #[test]
+    fn finds_game_by_file_name() {
+        let dat = dat_index();
+
+        let game = dat.find_by_name("Tetris (World) (Rev 1).gb", "Tetris (World) (Rev 1)").unwrap();
+        assert_eq!(game.description, "Tetris (World) (Rev 1)");
+        assert_eq!(game.year.as_deref(), Some("1989"));
+        assert_eq!(game.manufacturer.as_deref(), Some("Nintendo"));
+
+        let game = dat.find_by_name("tetris (japan).zip", "tetris (japan)").unwrap();
+        assert_eq!(game.clone_of.as_deref(), Some("Tetris (World) (Rev 1)"));
+    }
+
+    #[test]
+    fn finds_renamed_file_by_crc() {
+        let dat = dat_index();
+
+        assert!(dat.find_by_name("zelda.gb", "zelda").is_none());
+
+        let game = dat.find_by_crc(0x2ae2d6e5).unwrap();
+        assert_eq!(game.name, "Legend of Zelda, The - Link's Awakening (USA, Europe)");
+        assert!(dat.find_by_crc(0x12345678).is_none());
+    }
+}
\ No newline at end of file
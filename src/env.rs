@@ -136,6 +136,10 @@ impl Env {
     pub fn get_keymap_path(&self) -> PathBuf {
         self.state_dir.join("keymap.toml")
     }
+
+    pub fn get_launch_log_dir(&self) -> PathBuf {
+        self.state_dir.join("logs")
+    }
 }
 
 fn get_dir_file_pair(file_path: &Path) -> Result<(&OsStr, &Path)> {
@@ -0,0 +1,251 @@
+/*
+ * Lemon Launcher - SDL based MAME frontend for arcade cabinets
+ * Copyright (C) 2024 Josh Kropf <josh@slashdev.ca>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{collections::HashSet, time::{Duration, Instant}};
+
+use anyhow::{Error, Result};
+use sdl2::{
+    controller::GameController,
+    event::Event,
+    joystick::{HatState, Joystick},
+    GameControllerSubsystem, JoystickSubsystem, Sdl
+};
+
+use crate::keymap::{Hat, Input};
+
+/// Axis value past which an axis direction counts as pressed
+const AXIS_THRESHOLD: i16 = 16384;
+
+/// Milliseconds a controller input is held before it repeats
+const REPEAT_DELAY: u64 = 500;
+
+/// Milliseconds between repeats of a held controller input
+const REPEAT_INTERVAL: u64 = 50;
+
+pub enum InputEvent {
+    Down { input: Input, repeat: bool },
+    Up(Input)
+}
+
+/// Turns keyboard, game controller, joystick and mouse button events into inputs
+///
+/// Axes and hats are turned into presses of their directions. Controllers
+/// don't repeat like keys do, so held controller inputs are repeated here.
+pub struct InputEvents {
+    controller_subsystem: GameControllerSubsystem,
+    joystick_subsystem: JoystickSubsystem,
+    controllers: Vec<GameController>,
+    joysticks: Vec<Joystick>,
+    /// Axis and hat directions currently pressed
+    pressed: HashSet<Input>,
+    /// Controller input being held and when it next repeats
+    held: Option<(Input, Instant)>
+}
+
+impl InputEvents {
+    pub fn new(sdl: &Sdl) -> Result<Self> {
+        Ok(Self {
+            controller_subsystem: sdl.game_controller()
+                .map_err(Error::msg)?,
+            joystick_subsystem: sdl.joystick()
+                .map_err(Error::msg)?,
+            controllers: vec![],
+            joysticks: vec![],
+            pressed: HashSet::new(),
+            held: None
+        })
+    }
+
+    /// Open and close devices as they are connected and disconnected.
+    /// Devices connected at startup are also reported as added by SDL.
+    pub fn update_devices(&mut self, event: &Event) {
+        match event {
+            Event::ControllerDeviceAdded { which, .. } => {
+                match self.controller_subsystem.open(*which) {
+                    Ok(controller) => self.controllers.push(controller),
+                    Err(e) => println!("Unable to open game controller {}: {}", which, e)
+                }
+            },
+            // game controllers are opened by their controller event
+            Event::JoyDeviceAdded { which, .. }
+                if !self.controller_subsystem.is_game_controller(*which) =>
+            {
+                match self.joystick_subsystem.open(*which) {
+                    Ok(joystick) => self.joysticks.push(joystick),
+                    Err(e) => println!("Unable to open joystick {}: {}", which, e)
+                }
+            },
+            Event::ControllerDeviceRemoved { which, .. } => {
+                self.controllers.retain(|c| c.instance_id() != *which);
+            },
+            Event::JoyDeviceRemoved { which, .. } => {
+                self.joysticks.retain(|j| j.instance_id() != *which);
+            },
+            _ => ()
+        }
+    }
+
+    pub fn translate(&mut self, event: &Event) -> Vec<InputEvent> {
+        let events = match event {
+            Event::KeyDown { keycode: Some(keycode), repeat, .. } => {
+                vec![InputEvent::Down { input: (*keycode).into(), repeat: *repeat }]
+            },
+            Event::KeyUp { keycode: Some(keycode), .. } => {
+                vec![InputEvent::Up((*keycode).into())]
+            },
+            Event::ControllerButtonDown { button, .. } => {
+                vec![InputEvent::Down { input: Input::Button(*button), repeat: false }]
+            },
+            Event::ControllerButtonUp { button, .. } => {
+                vec![InputEvent::Up(Input::Button(*button))]
+            },
+            Event::ControllerAxisMotion { axis, value, .. } => {
+                self.axis_events(|positive| Input::Axis(*axis, positive), *value)
+            },
+            // game controllers also send joystick events
+            Event::JoyButtonDown { which, .. }
+            | Event::JoyButtonUp { which, .. }
+            | Event::JoyAxisMotion { which, .. }
+            | Event::JoyHatMotion { which, .. } if self.is_controller(*which) => vec![],
+            Event::JoyButtonDown { button_idx, .. } => {
+                vec![InputEvent::Down { input: Input::JoyButton(*button_idx), repeat: false }]
+            },
+            Event::JoyButtonUp { button_idx, .. } => {
+                vec![InputEvent::Up(Input::JoyButton(*button_idx))]
+            },
+            Event::JoyAxisMotion { axis_idx, value, .. } => {
+                self.axis_events(|positive| Input::JoyAxis(*axis_idx, positive), *value)
+            },
+            Event::JoyHatMotion { hat_idx, state, .. } => {
+                self.hat_events(*hat_idx, *state)
+            },
+            Event::MouseButtonDown { mouse_btn, .. } => {
+                vec![InputEvent::Down { input: Input::Mouse(*mouse_btn), repeat: false }]
+            },
+            Event::MouseButtonUp { mouse_btn, .. } => {
+                vec![InputEvent::Up(Input::Mouse(*mouse_btn))]
+            },
+            _ => vec![]
+        };
+
+        for event in &events {
+            match event {
+                InputEvent::Down { input: input @ Input::Key(_), .. } => {
+                    // keys repeat on their own
+                    if self.held.is_some_and(|(held, _)| held != *input) {
+                        self.held = None;
+                    }
+                },
+                InputEvent::Down { input, .. } => {
+                    let repeat_at = Instant::now() + Duration::from_millis(REPEAT_DELAY);
+                    self.held = Some((*input, repeat_at));
+                },
+                InputEvent::Up(input) => {
+                    if self.held.is_some_and(|(held, _)| held == *input) {
+                        self.held = None;
+                    }
+                }
+            }
+        }
+
+        events
+    }
+
+    /// Repeat of the held controller input, when one is due
+    pub fn next_repeat(&mut self) -> Option<InputEvent> {
+        let (input, repeat_at) = self.held.as_mut()?;
+
+        if Instant::now() < *repeat_at {
+            return None;
+        }
+
+        *repeat_at += Duration::from_millis(REPEAT_INTERVAL);
+
+        Some(InputEvent::Down { input: *input, repeat: true })
+    }
+
+    /// Milliseconds until the held controller input repeats
+    pub fn repeat_interval(&self) -> Option<u32> {
+        self.held.map(|(_, repeat_at)| {
+            repeat_at.saturating_duration_since(Instant::now())
+                .as_millis()
+                .max(1) as u32
+        })
+    }
+
+    /// Forget held inputs, for when events were missed while an emulator ran
+    pub fn release_all(&mut self) {
+        self.pressed.clear();
+        self.held = None;
+    }
+
+    fn is_controller(&self, instance_id: u32) -> bool {
+        self.controllers.iter()
+            .any(|c| c.instance_id() == instance_id)
+    }
+
+    fn axis_events(&mut self, input: impl Fn(bool) -> Input, value: i16) -> Vec<InputEvent> {
+        let pressed = if value <= -AXIS_THRESHOLD {
+            Some(input(false))
+        } else if value >= AXIS_THRESHOLD {
+            Some(input(true))
+        } else {
+            None
+        };
+
+        self.update_pressed(&[input(false), input(true)], pressed.as_slice())
+    }
+
+    fn hat_events(&mut self, index: u8, state: HatState) -> Vec<InputEvent> {
+        let directions = [
+            (HatState::Up, Hat::Up),
+            (HatState::Down, Hat::Down),
+            (HatState::Left, Hat::Left),
+            (HatState::Right, Hat::Right)
+        ];
+
+        let inputs: Vec<Input> = directions.iter()
+            .map(|(_, hat)| Input::JoyHat(index, *hat))
+            .collect();
+
+        // diagonals press two directions
+        let pressed: Vec<Input> = directions.iter()
+            .filter(|(bit, _)| state as u8 & *bit as u8 != 0)
+            .map(|(_, hat)| Input::JoyHat(index, *hat))
+            .collect();
+
+        self.update_pressed(&inputs, &pressed)
+    }
+
+    /// Events for `inputs` of an axis or hat that changed from the pressed set
+    fn update_pressed(&mut self, inputs: &[Input], pressed: &[Input]) -> Vec<InputEvent> {
+        let mut events = vec![];
+
+        for input in inputs {
+            if pressed.contains(input) {
+                if self.pressed.insert(*input) {
+                    events.push(InputEvent::Down { input: *input, repeat: false });
+                }
+            } else if self.pressed.remove(input) {
+                events.push(InputEvent::Up(*input));
+            }
+        }
+
+        events
+    }
+}
\ No newline at end of file
@@ -16,18 +16,22 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::{collections::HashMap, fs, path::Path};
+use std::{collections::HashMap, fmt, fs, path::Path, time::{Duration, Instant}};
 
-use anyhow::Result;
-use sdl2::keyboard::Keycode as SdlKeycode;
+use anyhow::{anyhow, Error, Result};
+use sdl2::{
+    controller::{Axis, Button},
+    keyboard::Keycode as SdlKeycode,
+    mouse::MouseButton
+};
 use serde::{Deserialize, Serialize};
 
+#[derive(Clone)]
 pub struct Keymap {
-    keymap: ActionToKeycode
+    keymap: ActionToBindings
 }
 
-pub type ActionToKeycode = HashMap<Action, Keycode>;
-pub type SdlKeycodeToAction = HashMap<SdlKeycode, Action>;
+pub type ActionToBindings = HashMap<Action, Bindings>;
 
 impl Keymap {
     pub fn load(file_path: impl AsRef<Path>) -> Result<Keymap> {
@@ -39,7 +43,11 @@ impl Keymap {
         }
     }
 
-    pub fn save(keymap: &ActionToKeycode, file_path: impl AsRef<Path>) -> Result<()> {
+    pub fn into_bindings(self) -> ActionToBindings {
+        self.keymap
+    }
+
+    pub fn save(keymap: &ActionToBindings, file_path: impl AsRef<Path>) -> Result<()> {
         let toml_src = toml::to_string(keymap)?;
         Ok(fs::write(file_path, toml_src)?)
     }
@@ -49,51 +57,411 @@ impl Default for Keymap {
     fn default() -> Self {
         Self {
             keymap: HashMap::from([
-                (Action::CursorUp, SdlKeycode::Up.into()),
-                (Action::CursorDown, SdlKeycode::Down.into()),
-                (Action::PageUp, SdlKeycode::Left.into()),
-                (Action::PageDown, SdlKeycode::Right.into()),
-                (Action::Select, SdlKeycode::Return.into()),
-                (Action::Back, SdlKeycode::Backspace.into()),
-                (Action::Favourite, SdlKeycode::F.into())
+                (Action::CursorUp, Bindings::inputs([
+                    SdlKeycode::Up.into(),
+                    Input::Button(Button::DPadUp),
+                    Input::Axis(Axis::LeftY, false),
+                    Input::JoyHat(0, Hat::Up)
+                ])),
+                (Action::CursorDown, Bindings::inputs([
+                    SdlKeycode::Down.into(),
+                    Input::Button(Button::DPadDown),
+                    Input::Axis(Axis::LeftY, true),
+                    Input::JoyHat(0, Hat::Down)
+                ])),
+                (Action::PageUp, Bindings::inputs([
+                    SdlKeycode::Left.into(),
+                    Input::Button(Button::DPadLeft),
+                    Input::Axis(Axis::LeftX, false),
+                    Input::JoyHat(0, Hat::Left)
+                ])),
+                (Action::PageDown, Bindings::inputs([
+                    SdlKeycode::Right.into(),
+                    Input::Button(Button::DPadRight),
+                    Input::Axis(Axis::LeftX, true),
+                    Input::JoyHat(0, Hat::Right)
+                ])),
+                (Action::Select, Bindings::inputs([
+                    SdlKeycode::Return.into(),
+                    Input::Button(Button::A),
+                    Input::JoyButton(0),
+                    Input::Mouse(MouseButton::Left)
+                ])),
+                (Action::Back, Bindings::inputs([
+                    SdlKeycode::Backspace.into(),
+                    Input::Button(Button::B),
+                    Input::JoyButton(1),
+                    Input::Mouse(MouseButton::Right)
+                ])),
+                (Action::Favourite, Bindings::inputs([
+                    SdlKeycode::F.into(),
+                    Input::Button(Button::Y)
+                ])),
+                (Action::JumpNextLetter, Bindings::inputs([
+                    SdlKeycode::PageDown.into(),
+                    Input::Button(Button::RightShoulder)
+                ])),
+                (Action::JumpPrevLetter, Bindings::inputs([
+                    SdlKeycode::PageUp.into(),
+                    Input::Button(Button::LeftShoulder)
+                ]))
             ])
         }
     }
 }
 
-impl From<Keymap> for SdlKeycodeToAction {
+impl From<Keymap> for InputDispatcher {
     fn from(keymap: Keymap) -> Self {
-        keymap.keymap.into_iter()
-            .flat_map(|(action, keycode)| {
-                match keycode {
-                    Keycode::Single(k) => vec![(
-                        SdlKeycode::from_i32(k).unwrap(),
-                        action
-                    )],
-                    Keycode::Many(keys) => keys.into_iter()
-                        .map(|k| (
-                            SdlKeycode::from_i32(k).unwrap(),
-                            action.clone()
-                        ))
-                        .collect()
+        let mut dispatcher = InputDispatcher::default();
+
+        for (action, bindings) in keymap.keymap {
+            let bindings = match bindings {
+                Bindings::Single(binding) => vec![binding],
+                Bindings::Many(bindings) => bindings
+            };
+
+            for binding in bindings {
+                match binding {
+                    Binding::Input(input) => {
+                        dispatcher.inputs.insert(input, action.clone());
+                    },
+                    Binding::Chord { chord } => {
+                        dispatcher.chords.push((chord, action.clone()));
+                    },
+                    Binding::Hold { hold, duration } => {
+                        let duration = Duration::from_millis(duration as u64);
+                        dispatcher.holds.insert(hold, (duration, action.clone()));
+                    }
+                }
+            }
+        }
+
+        dispatcher
+    }
+}
+
+/// One or more bindings mapped to an action
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum Bindings {
+    Single(Binding),
+    Many(Vec<Binding>)
+}
+
+impl Bindings {
+    fn inputs<const N: usize>(inputs: [Input; N]) -> Self {
+        Self::Many(inputs.into_iter().map(Binding::Input).collect())
+    }
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+#[serde(untagged)]
+pub enum Binding {
+    Input(Input),
+    /// Inputs pressed together, e.g. `{ chord = ["joy_button:8", "joy_button:9"] }`
+    Chord {
+        chord: Vec<Input>
+    },
+    /// Input held for `duration` milliseconds, e.g. `{ hold = 13, duration = 2000 }`
+    Hold {
+        hold: Input,
+        #[serde(default = "default_hold_duration")]
+        duration: u32
+    }
+}
+
+pub fn default_hold_duration() -> u32 {
+    2000
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Binding::Input(input) => write!(f, "{}", input),
+            Binding::Chord { chord } => {
+                let inputs: Vec<String> = chord.iter()
+                    .map(|i| i.to_string())
+                    .collect();
+                write!(f, "{}", inputs.join(" + "))
+            },
+            Binding::Hold { hold, duration } => write!(f, "hold {} {}ms", hold, duration)
+        }
+    }
+}
+
+/// Turns input presses and releases into actions
+///
+/// An input bound to a long press only fires its plain action when released
+/// before the long press is due. Inputs used by a chord or long press don't
+/// fire anything else until they are released.
+#[derive(Default)]
+pub struct InputDispatcher {
+    inputs: HashMap<Input, Action>,
+    chords: Vec<(Vec<Input>, Action)>,
+    holds: HashMap<Input, (Duration, Action)>,
+    pressed: Vec<PressedInput>
+}
+
+struct PressedInput {
+    input: Input,
+    pressed_at: Instant,
+    /// Used by a chord or long press
+    used: bool
+}
+
+impl InputDispatcher {
+    pub fn press(&mut self, input: Input, repeat: bool) -> Option<Action> {
+        if repeat {
+            // long presses are fired by `poll`
+            let used = self.pressed.iter()
+                .any(|p| p.input == input && p.used);
+
+            if used || self.is_deferred(&input) {
+                return None;
+            }
+
+            return self.inputs.get(&input).cloned();
+        }
+
+        self.pressed.retain(|p| p.input != input);
+        self.pressed.push(PressedInput { input, pressed_at: Instant::now(), used: false });
+
+        let chord = self.chords.iter()
+            .find(|(chord, _)| {
+                chord.contains(&input) && chord.iter().all(|i| self.is_pressed(i))
+            })
+            .cloned();
+
+        if let Some((chord, action)) = chord {
+            for pressed in self.pressed.iter_mut().filter(|p| chord.contains(&p.input)) {
+                pressed.used = true;
+            }
+
+            return Some(action);
+        }
+
+        // wait for release, long press or the rest of a chord
+        if self.is_deferred(&input) {
+            return None;
+        }
+
+        self.inputs.get(&input).cloned()
+    }
+
+    pub fn release(&mut self, input: Input) -> Option<Action> {
+        let index = self.pressed.iter().position(|p| p.input == input)?;
+        let pressed = self.pressed.remove(index);
+
+        // short press of an input with a long press or chord binding
+        if !pressed.used && self.is_deferred(&input) {
+            return self.inputs.get(&input).cloned();
+        }
+
+        None
+    }
+
+    /// Actions of long presses that are due
+    pub fn poll(&mut self) -> Vec<Action> {
+        let mut actions = vec![];
+
+        for pressed in self.pressed.iter_mut().filter(|p| !p.used) {
+            if let Some((duration, action)) = self.holds.get(&pressed.input) {
+                if pressed.pressed_at.elapsed() >= *duration {
+                    pressed.used = true;
+                    actions.push(action.clone());
                 }
+            }
+        }
+
+        actions
+    }
+
+    /// Milliseconds until the next long press is due
+    pub fn next_timeout(&self) -> Option<u32> {
+        self.pressed.iter()
+            .filter(|p| !p.used)
+            .filter_map(|p| {
+                let (duration, _) = self.holds.get(&p.input)?;
+                let due = duration.saturating_sub(p.pressed_at.elapsed());
+                Some(due.as_millis().max(1) as u32)
             })
-            .collect()
+            .min()
+    }
+
+    /// Forget pressed inputs, for when releases were missed
+    pub fn release_all(&mut self) {
+        self.pressed.clear();
+    }
+
+    fn is_pressed(&self, input: &Input) -> bool {
+        self.pressed.iter().any(|p| p.input == *input)
+    }
+
+    /// Plain actions of inputs that are part of long presses or chords fire
+    /// on release, once it's known the input wasn't used by them
+    fn is_deferred(&self, input: &Input) -> bool {
+        self.holds.contains_key(input)
+            || self.chords.iter().any(|(chord, _)| chord.contains(input))
+    }
+}
+
+/// Keyboard key, game controller, joystick or mouse input
+///
+/// Keys are stored in the keymap as integers defined in SDL_KeyCode
+/// https://github.com/Rust-SDL2/rust-sdl2/blob/master/sdl2-sys/sdl_bindings.rs#L7659
+///
+/// Other inputs are stored as strings, e.g. "button:a", "axis:lefty-",
+/// "joy_button:3", "joy_axis:1+", "joy_hat:0:up" or "mouse:left"
+#[derive(Deserialize, Serialize, Eq, PartialEq, Hash, Clone, Copy, Debug)]
+#[serde(try_from = "InputValue", into = "InputValue")]
+pub enum Input {
+    Key(i32),
+    /// Game controller button
+    Button(Button),
+    /// Game controller axis, true for the positive direction
+    Axis(Axis, bool),
+    /// Button index of a joystick that isn't a game controller
+    JoyButton(u8),
+    /// Axis index of a joystick, true for the positive direction
+    JoyAxis(u8, bool),
+    /// Hat index and direction of a joystick
+    JoyHat(u8, Hat),
+    Mouse(MouseButton)
+}
+
+#[derive(Eq, PartialEq, Hash, Clone, Copy, Debug)]
+pub enum Hat {
+    Up,
+    Down,
+    Left,
+    Right
+}
+
+impl Hat {
+    fn name(&self) -> &'static str {
+        match self {
+            Hat::Up => "up",
+            Hat::Down => "down",
+            Hat::Left => "left",
+            Hat::Right => "right"
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Hat> {
+        match name {
+            "up" => Some(Hat::Up),
+            "down" => Some(Hat::Down),
+            "left" => Some(Hat::Left),
+            "right" => Some(Hat::Right),
+            _ => None
+        }
+    }
+}
+
+fn mouse_button_name(button: MouseButton) -> &'static str {
+    match button {
+        MouseButton::Left => "left",
+        MouseButton::Middle => "middle",
+        MouseButton::Right => "right",
+        MouseButton::X1 => "x1",
+        MouseButton::X2 => "x2",
+        MouseButton::Unknown => "unknown"
+    }
+}
+
+fn mouse_button_from_name(name: &str) -> Option<MouseButton> {
+    match name {
+        "left" => Some(MouseButton::Left),
+        "middle" => Some(MouseButton::Middle),
+        "right" => Some(MouseButton::Right),
+        "x1" => Some(MouseButton::X1),
+        "x2" => Some(MouseButton::X2),
+        _ => None
+    }
+}
+
+impl From<SdlKeycode> for Input {
+    fn from(value: SdlKeycode) -> Self {
+        Self::Key(*value)
+    }
+}
+
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = |positive: &bool| if *positive { "+" } else { "-" };
+
+        match self {
+            Input::Key(key) => match SdlKeycode::from_i32(*key) {
+                Some(keycode) => write!(f, "key:{}", keycode.name()),
+                None => write!(f, "key:{}", key)
+            },
+            Input::Button(button) => write!(f, "button:{}", button.string()),
+            Input::Axis(axis, positive) => write!(f, "axis:{}{}", axis.string(), sign(positive)),
+            Input::JoyButton(index) => write!(f, "joy_button:{}", index),
+            Input::JoyAxis(index, positive) => write!(f, "joy_axis:{}{}", index, sign(positive)),
+            Input::JoyHat(index, hat) => write!(f, "joy_hat:{}:{}", index, hat.name()),
+            Input::Mouse(button) => write!(f, "mouse:{}", mouse_button_name(*button))
+        }
     }
 }
 
-// Keycodes are integers defined in SDL_KeyCode
-// https://github.com/Rust-SDL2/rust-sdl2/blob/master/sdl2-sys/sdl_bindings.rs#L7659
+/// Keymap file value of an input
 #[derive(Deserialize, Serialize)]
 #[serde(untagged)]
-pub enum Keycode {
-    Single(i32),
-    Many(Vec<i32>)
+enum InputValue {
+    Key(i32),
+    Other(String)
 }
 
-impl From<SdlKeycode> for Keycode {
-    fn from(value: SdlKeycode) -> Self {
-        Self::Single(*value)
+impl From<Input> for InputValue {
+    fn from(input: Input) -> Self {
+        match input {
+            Input::Key(key) => InputValue::Key(key),
+            other => InputValue::Other(other.to_string())
+        }
+    }
+}
+
+impl TryFrom<InputValue> for Input {
+    type Error = Error;
+
+    fn try_from(value: InputValue) -> Result<Self> {
+        let value = match value {
+            InputValue::Key(key) => return Ok(Input::Key(key)),
+            InputValue::Other(value) => value
+        };
+
+        let invalid = || anyhow!("Invalid input {}", value);
+
+        // axis values end with the direction
+        let split_sign = |s: &str| match s.char_indices().last() {
+            Some((i, '+')) => Some((s[..i].to_string(), true)),
+            Some((i, '-')) => Some((s[..i].to_string(), false)),
+            _ => None
+        };
+
+        let (kind, arg) = value.split_once(':').ok_or_else(invalid)?;
+
+        let input = match kind {
+            "button" => Button::from_string(arg).map(Input::Button),
+            "axis" => split_sign(arg)
+                .and_then(|(axis, positive)| {
+                    Axis::from_string(&axis).map(|axis| Input::Axis(axis, positive))
+                }),
+            "joy_button" => arg.parse().ok().map(Input::JoyButton),
+            "joy_axis" => split_sign(arg)
+                .and_then(|(index, positive)| {
+                    index.parse().ok().map(|index| Input::JoyAxis(index, positive))
+                }),
+            "joy_hat" => arg.split_once(':')
+                .and_then(|(index, hat)| {
+                    Some(Input::JoyHat(index.parse().ok()?, Hat::from_name(hat)?))
+                }),
+            "mouse" => mouse_button_from_name(arg).map(Input::Mouse),
+            _ => None
+        };
+
+        input.ok_or_else(invalid)
     }
 }
 
@@ -106,5 +474,144 @@ pub enum Action {
     PageDown,
     Select,
     Back,
-    Favourite
+    Favourite,
+    JumpNextLetter,
+    JumpPrevLetter,
+    /// Quit the launcher
+    Exit,
+    /// Stop the running emulator
+    KillEmulator
+}
+
+impl Action {
+    /// All actions in the order they are shown by the keymap editor
+    pub fn all() -> Vec<Action> {
+        vec![
+            Action::CursorUp,
+            Action::CursorDown,
+            Action::PageUp,
+            Action::PageDown,
+            Action::Select,
+            Action::Back,
+            Action::Favourite,
+            Action::JumpNextLetter,
+            Action::JumpPrevLetter,
+            Action::Exit,
+            Action::KillEmulator
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_keys_and_joystick_inputs() {
+        let keymap: ActionToBindings = toml::from_str(r#"
+            cursor_up = 1073741906
+            back = "mouse:right"
+            select = [13, "joy_button:2"]
+            page_up = ["joy_axis:0-", "joy_hat:1:left"]
+        "#).unwrap();
+
+        let inputs = |action| match &keymap[&action] {
+            Bindings::Single(binding) => vec![binding.clone()],
+            Bindings::Many(bindings) => bindings.clone()
+        };
+
+        assert_eq!(inputs(Action::CursorUp), [Binding::Input(Input::Key(1073741906))]);
+        assert_eq!(inputs(Action::Back), [Binding::Input(Input::Mouse(MouseButton::Right))]);
+        assert_eq!(inputs(Action::Select), [
+            Binding::Input(Input::Key(13)),
+            Binding::Input(Input::JoyButton(2))
+        ]);
+        assert_eq!(inputs(Action::PageUp), [
+            Binding::Input(Input::JoyAxis(0, false)),
+            Binding::Input(Input::JoyHat(1, Hat::Left))
+        ]);
+    }
+
+    #[test]
+    fn rejects_invalid_inputs() {
+        for value in [
+            "joy_button", "joy_button:x", "joy_axis:1", "joy_hat:0:middle", "mouse:unknown", "pedal:1"
+        ] {
+            let input = Input::try_from(InputValue::Other(value.to_string()));
+            assert!(input.is_err(), "{} should be invalid", value);
+        }
+    }
+
+    #[test]
+    fn writes_joystick_inputs_as_strings() {
+        let keymap = ActionToBindings::from([
+            (Action::Back, Bindings::Single(Binding::Input(Input::JoyAxis(3, true))))
+        ]);
+
+        let toml_src = toml::to_string(&keymap).unwrap();
+
+        assert_eq!(toml_src.trim(), r#"back = "joy_axis:3+""#);
+    }
+
+    fn dispatcher(keymap_src: &str) -> InputDispatcher {
+        let keymap = toml::from_str(keymap_src).unwrap();
+        Keymap { keymap }.into()
+    }
+
+    #[test]
+    fn fires_chord_when_all_inputs_pressed() {
+        let mut dispatcher = dispatcher(r#"
+            back = { chord = ["joy_button:8", "joy_button:9"] }
+        "#);
+
+        assert_eq!(dispatcher.press(Input::JoyButton(8), false), None);
+        assert_eq!(dispatcher.press(Input::JoyButton(9), false), Some(Action::Back));
+        assert_eq!(dispatcher.release(Input::JoyButton(8)), None);
+        assert_eq!(dispatcher.release(Input::JoyButton(9)), None);
+    }
+
+    #[test]
+    fn fires_plain_action_of_chord_input_on_release() {
+        let mut dispatcher = dispatcher(r#"
+            back = "joy_button:8"
+            exit = { chord = ["joy_button:8", "joy_button:9"] }
+        "#);
+
+        assert_eq!(dispatcher.press(Input::JoyButton(8), false), None);
+        assert_eq!(dispatcher.press(Input::JoyButton(8), true), None);
+        assert_eq!(dispatcher.release(Input::JoyButton(8)), Some(Action::Back));
+
+        assert_eq!(dispatcher.press(Input::JoyButton(8), false), None);
+        assert_eq!(dispatcher.press(Input::JoyButton(9), false), Some(Action::Exit));
+        assert_eq!(dispatcher.release(Input::JoyButton(8)), None);
+        assert_eq!(dispatcher.release(Input::JoyButton(9)), None);
+    }
+
+    #[test]
+    fn fires_plain_action_on_short_press_of_hold_input() {
+        let mut dispatcher = dispatcher(r#"
+            select = 13
+            favourite = { hold = 13, duration = 60000 }
+        "#);
+
+        assert_eq!(dispatcher.press(Input::Key(13), false), None);
+        assert_eq!(dispatcher.press(Input::Key(13), true), None);
+        assert!(dispatcher.poll().is_empty());
+        assert!(dispatcher.next_timeout().is_some());
+        assert_eq!(dispatcher.release(Input::Key(13)), Some(Action::Select));
+    }
+
+    #[test]
+    fn fires_long_press_once() {
+        let mut dispatcher = dispatcher(r#"
+            select = 13
+            favourite = { hold = 13, duration = 0 }
+        "#);
+
+        assert_eq!(dispatcher.press(Input::Key(13), false), None);
+        assert_eq!(dispatcher.poll(), [Action::Favourite]);
+        assert!(dispatcher.poll().is_empty());
+        assert_eq!(dispatcher.next_timeout(), None);
+        assert_eq!(dispatcher.release(Input::Key(13)), None);
+    }
 }
\ No newline at end of file
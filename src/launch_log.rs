@@ -0,0 +1,129 @@
+/*
+ * Lemon Launcher - SDL based MAME frontend for arcade cabinets
+ * Copyright (C) 2024 Josh Kropf <josh@slashdev.ca>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH}
+};
+
+use anyhow::Result;
+
+use crate::env::Env;
+
+/// Number of launch logs kept in the log dir
+const KEEP_LOGS: usize = 20;
+
+/// Output of a launched emulator, written to a file in the state dir
+pub struct LaunchLog {
+    path: PathBuf,
+    file: File
+}
+
+impl LaunchLog {
+    /// Create log file for a launch of `rom`, removing the oldest logs
+    pub fn create(env: &Env, rom: &str) -> Result<Self> {
+        let log_dir = env.get_launch_log_dir();
+        fs::create_dir_all(&log_dir)?;
+
+        remove_old_logs(&log_dir)?;
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)?
+            .as_secs();
+
+        let path = log_dir.join(format!("{}-{}.log", timestamp, rom));
+        let file = File::create(&path)?;
+
+        Ok(Self { path, file })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Send stdout and stderr of `cmd` to the log, after a line with the command
+    pub fn attach(&mut self, cmd: &mut Command) -> Result<()> {
+        writeln!(self.file, "{:?}", cmd)?;
+
+        cmd.stdout(self.file.try_clone()?);
+        cmd.stderr(self.file.try_clone()?);
+
+        Ok(())
+    }
+
+    /// Last `count` lines of output that aren't blank
+    pub fn tail(&self, count: usize) -> Result<Vec<String>> {
+        let output = fs::read(&self.path)?;
+        let output = String::from_utf8_lossy(&output);
+
+        let mut lines: Vec<String> = output.lines()
+            .map(|l| l.trim_end())
+            .filter(|l| !l.is_empty())
+            .rev()
+            .take(count)
+            .map(String::from)
+            .collect();
+
+        lines.reverse();
+
+        Ok(lines)
+    }
+}
+
+/// Remove logs so that `KEEP_LOGS` remain once a new log is added, log
+/// names start with a timestamp so they sort oldest first
+fn remove_old_logs(log_dir: &Path) -> Result<()> {
+    let mut logs: Vec<PathBuf> = fs::read_dir(log_dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "log"))
+        .collect();
+
+    logs.sort();
+
+    let remove_count = (logs.len() + 1).saturating_sub(KEEP_LOGS);
+
+    for log in logs.iter().take(remove_count) {
+        fs::remove_file(log)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_last_lines_of_output() {
+        let path = std::env::temp_dir()
+            .join(format!("lemon-launcher-test-{}.log", std::process::id()));
+
+        fs::write(&path, "line 1\nline 2\n\nline 3  \r\nline 4\n\n").unwrap();
+
+        let log = LaunchLog { file: File::open(&path).unwrap(), path: path.clone() };
+        let tail = log.tail(3).unwrap();
+
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(tail, ["line 2", "line 3", "line 4"]);
+    }
+}
\ No newline at end of file
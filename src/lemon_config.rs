@@ -16,12 +16,15 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use sdl2::rect::Rect;
 use serde::Deserialize;
 use std::{collections::HashMap, fs, path::{Path, PathBuf}};
 
-use crate::{env::Env, lemon_launcher::ConfigError};
+use crate::{env::Env, lemon_launcher::ConfigError, scan::Exclude};
+
+/// Name of the `mame` command, used by roms without an emulator
+pub const DEFAULT_EMULATOR: &str = "mame";
 
 #[derive(Deserialize, Clone)]
 pub struct LemonConfig {
@@ -31,6 +34,23 @@ pub struct LemonConfig {
     pub background: Option<Background>,
     pub menu: LemonMenuConfig,
     pub mame: ExecCommand,
+    #[serde(default = "HashMap::new")]
+    pub emulators: HashMap<String, Emulator>,
+    pub rom_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub scan: ScanConfig,
+    #[serde(default)]
+    pub clones: CloneListing,
+    /// Cursor movement by mouse, trackball or spinner motion
+    pub mouse: Option<MouseConfig>,
+    #[serde(default)]
+    pub supervisor: SupervisorConfig,
+    /// Commands run before launching a rom
+    #[serde(default = "Vec::new")]
+    pub pre_launch: Vec<ExecCommand>,
+    /// Commands run after the emulator exits
+    #[serde(default = "Vec::new")]
+    pub post_launch: Vec<ExecCommand>,
     #[serde(default = "Vec::new")]
     pub widgets: Vec<Widget>
 }
@@ -44,6 +64,40 @@ impl LemonConfig {
         Ok(config)
     }
 
+    /// Get command of named emulator, `mame` when no name is given or no
+    /// emulator of that name is configured
+    pub fn get_emulator(&self, name: Option<&str>) -> Result<&ExecCommand> {
+        match (name, self.get_named_emulator(name)) {
+            (_, Some(emulator)) => Ok(&emulator.command),
+            (None | Some(DEFAULT_EMULATOR), None) => Ok(&self.mame),
+            (Some(name), None) => Err(anyhow!("Emulator {} not found in config", name))
+        }
+    }
+
+    /// Check if the named emulator is a mame build that takes `-rompath`
+    pub fn is_mame(&self, name: Option<&str>) -> bool {
+        self.get_named_emulator(name)
+            .is_none_or(|e| e.mame)
+    }
+
+    /// Get commands run before launching with the named emulator
+    pub fn get_pre_launch(&self, emulator: Option<&str>) -> &[ExecCommand] {
+        self.get_named_emulator(emulator)
+            .and_then(|e| e.pre_launch.as_deref())
+            .unwrap_or(&self.pre_launch)
+    }
+
+    /// Get commands run after the named emulator exits
+    pub fn get_post_launch(&self, emulator: Option<&str>) -> &[ExecCommand] {
+        self.get_named_emulator(emulator)
+            .and_then(|e| e.post_launch.as_deref())
+            .unwrap_or(&self.post_launch)
+    }
+
+    fn get_named_emulator(&self, name: Option<&str>) -> Option<&Emulator> {
+        name.and_then(|name| self.emulators.get(name))
+    }
+
     pub fn get_ui_size(&self) -> Size {
         self.ui_size.as_ref()
             .unwrap_or(&self.size)
@@ -55,6 +109,34 @@ fn default_field_template() -> String {
     String::from("{}")
 }
 
+fn default_scroll_interval() -> u32 {
+    5000
+}
+
+fn default_kill_wait() -> u32 {
+    5000
+}
+
+fn default_mouse_sensitivity() -> u32 {
+    20
+}
+
+fn default_acceleration_delay() -> u32 {
+    500
+}
+
+fn default_acceleration_max_step() -> u32 {
+    16
+}
+
+fn default_jump_duration() -> u32 {
+    1000
+}
+
+fn default_jump_justify() -> Justify {
+    Justify::Center
+}
+
 #[derive(Deserialize, Clone)]
 pub struct Font {
     pub file: PathBuf,
@@ -81,6 +163,79 @@ pub struct ExecCommand {
     pub args: Option<Vec<String>>
 }
 
+#[derive(Deserialize, Clone)]
+pub struct Emulator {
+    #[serde(flatten)]
+    pub command: ExecCommand,
+    /// File extensions of roms found by `scan-dir`
+    #[serde(default = "Vec::new")]
+    pub extensions: Vec<String>,
+    /// Emulator is a mame build, passed the rom dirs found by `scan`
+    #[serde(default)]
+    pub mame: bool,
+    /// Commands run before launching, in place of `pre_launch` of the config
+    pub pre_launch: Option<Vec<ExecCommand>>,
+    /// Commands run after the emulator exits, in place of `post_launch` of the config
+    pub post_launch: Option<Vec<ExecCommand>>
+}
+
+/// How clones of roms are shown in rom lists
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+pub enum CloneListing {
+    /// Selecting a parent rom opens a list of the parent and its clones
+    #[default]
+    #[serde(rename = "sub_list")]
+    SubList,
+    /// Clones are listed along with parent roms
+    #[serde(rename = "inline")]
+    Inline
+}
+
+/// Stopping of emulators and commands that hang
+#[derive(Deserialize, Clone)]
+pub struct SupervisorConfig {
+    /// Seconds an emulator or command can run before it's stopped
+    pub timeout: Option<u64>,
+    /// Seconds a launch hook can run before it's stopped
+    pub hook_timeout: Option<u64>,
+    /// Milliseconds to wait after SIGTERM before sending SIGKILL
+    #[serde(default = "default_kill_wait")]
+    pub kill_wait: u32
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self { timeout: None, hook_timeout: None, kill_wait: default_kill_wait() }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct MouseConfig {
+    /// Pixels of motion that move the cursor one row
+    #[serde(default = "default_mouse_sensitivity")]
+    pub sensitivity: u32,
+    #[serde(default)]
+    pub axis: MouseAxis
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+pub enum MouseAxis {
+    /// Horizontal motion, for spinners, right moves down
+    #[serde(rename = "x")]
+    X,
+    /// Vertical motion, for trackballs and mice
+    #[default]
+    #[serde(rename = "y")]
+    Y
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub struct ScanConfig {
+    /// Kinds of machines left out of the rom library
+    #[serde(default = "Vec::new")]
+    pub exclude: Vec<Exclude>
+}
+
 #[derive(Deserialize, Clone)]
 pub struct LemonMenuConfig {
     pub focus_offset: u32,
@@ -89,7 +244,32 @@ pub struct LemonMenuConfig {
     pub size: Size,
     pub justify: Justify,
     pub text_color: Color,
-    focus_color: Option<Color>
+    focus_color: Option<Color>,
+    /// Move from the last entry to the first and back again
+    #[serde(default)]
+    pub wrap: bool,
+    /// Speed up cursor movement while a key is held
+    pub acceleration: Option<Acceleration>
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Acceleration {
+    /// Milliseconds a key is held before the cursor step doubles
+    #[serde(default = "default_acceleration_delay")]
+    pub delay: u32,
+    /// Largest number of rows moved by a single key repeat
+    #[serde(default = "default_acceleration_max_step")]
+    pub max_step: u32
+}
+
+impl Acceleration {
+    /// Rows to move for a key held for `held_ms` milliseconds
+    pub fn step(&self, held_ms: u128) -> i32 {
+        let doublings = (held_ms / self.delay.max(1) as u128).min(31) as u32;
+
+        2u32.saturating_pow(doublings)
+            .min(self.max_step.max(1)) as i32
+    }
 }
 
 impl LemonMenuConfig {
@@ -164,7 +344,21 @@ pub enum WidgetField {
     #[serde(rename = "year")]
     Year,
     #[serde(rename = "manufacturer")]
-    Manufacturer
+    Manufacturer,
+    #[serde(rename = "sub_category")]
+    SubCategory,
+    #[serde(rename = "players")]
+    Players,
+    #[serde(rename = "controls")]
+    Controls,
+    #[serde(rename = "rating")]
+    Rating,
+    #[serde(rename = "num_players")]
+    NumPlayers,
+    #[serde(rename = "num_buttons")]
+    NumButtons,
+    #[serde(rename = "resolution")]
+    Resolution
 }
 
 #[derive(Deserialize, Clone)]
@@ -181,7 +375,11 @@ pub enum WidgetContent {
     #[serde(rename = "favourite")]
     Favourite {
         yes_image: PathBuf
-    }
+    },
+    #[serde(rename = "history")]
+    History(HistoryWidget),
+    #[serde(rename = "jump_letter")]
+    JumpLetter(JumpLetterWidget)
 }
 
 #[derive(Deserialize, Clone)]
@@ -194,6 +392,52 @@ pub struct TextWidget {
     pub justify: Justify
 }
 
+/// Word wrapped history.dat or mameinfo.dat text of the selected rom
+#[derive(Deserialize, Clone)]
+pub struct HistoryWidget {
+    #[serde(default)]
+    pub source: HistorySource,
+    #[serde(default)]
+    pub scroll: HistoryScroll,
+    /// Milliseconds between pages, or between lines when auto scrolling
+    #[serde(default = "default_scroll_interval")]
+    pub interval: u32,
+    pub line_height: Option<u32>,
+    pub text_color: Option<Color>,
+    #[serde(default)]
+    pub justify: Justify
+}
+
+/// Letter or decade of the selected entry, shown for a moment after a jump
+#[derive(Deserialize, Clone)]
+pub struct JumpLetterWidget {
+    /// Milliseconds to show the letter after jumping
+    #[serde(default = "default_jump_duration")]
+    pub duration: u32,
+    pub text_color: Option<Color>,
+    pub background: Option<Color>,
+    #[serde(default = "default_jump_justify")]
+    pub justify: Justify
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum HistorySource {
+    #[default]
+    #[serde(rename = "history")]
+    History,
+    #[serde(rename = "mameinfo")]
+    MameInfo
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub enum HistoryScroll {
+    #[default]
+    #[serde(rename = "page")]
+    Page,
+    #[serde(rename = "auto")]
+    Auto
+}
+
 #[derive(Deserialize, Clone)]
 pub struct ScreenshotWidget {
     pub dir: PathBuf,
@@ -16,72 +16,472 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::{collections::VecDeque, path::PathBuf};
+use std::{path::PathBuf, time::Instant};
 
 use anyhow::Result;
-use sdl2::{keyboard::Keycode, pixels::Color, rect::Rect};
+use sdl2::{keyboard::Keycode as SdlKeycode, pixels::Color, rect::Rect};
 
 use crate::{
-    keymap::{Action, ActionToKeycode, Keymap}, lemon_config::Justify,
+    keymap::{Action, ActionToBindings, Binding, Bindings, Input, InputDispatcher, Keymap},
+    lemon_config::Justify,
     lemon_screen::{EventReply, LemonScreen}, renderer::Renderer, MainLoopContext
 };
 
+/// Keymap editor listing the bindings of each action
+///
+/// Rows are the actions followed by save and quit. Columns of an action row
+/// are its bindings followed by a slot for adding a binding.
 pub struct LemonKeymap {
     file_path: PathBuf,
-    actions: VecDeque<Action>,
-    keymap: ActionToKeycode
+    bindings: Vec<(Action, Vec<Binding>)>,
+    /// Keymap being edited, used to move around the editor
+    dispatcher: InputDispatcher,
+    mode: Mode,
+    row: usize,
+    column: usize,
+    modified: bool,
+    /// Inputs pressed since all inputs were last released
+    recording: Vec<Input>,
+    /// Inputs still held down
+    pressed: Vec<Input>,
+    recording_at: Instant
 }
 
+enum Mode {
+    Browse,
+    /// Recording a binding for the selected column
+    Record,
+    ConfirmSave,
+    ConfirmQuit
+}
+
+/// Milliseconds an input is held before it's recorded as a long press
+const LONG_PRESS: u128 = 1000;
+
+const LINE_HEIGHT: u32 = 24;
+const ACTION_WIDTH: u32 = 200;
+
 impl LemonKeymap {
-    pub fn new(file_path: PathBuf) -> Self {
-        let actions = vec![
-            Action::CursorUp,
-            Action::CursorDown,
-            Action::PageUp,
-            Action::PageDown,
-            Action::Select,
-            Action::Back,
-            Action::Favourite
-        ];
-
-        Self {
+    pub fn new(file_path: PathBuf) -> Result<Self> {
+        let keymap = Keymap::load(&file_path)?;
+        let mut saved = keymap.clone().into_bindings();
+
+        let bindings = Action::all().into_iter()
+            .map(|action| {
+                let bindings = match saved.remove(&action) {
+                    Some(Bindings::Single(binding)) => vec![binding],
+                    Some(Bindings::Many(bindings)) => bindings,
+                    None => vec![]
+                };
+                (action, bindings)
+            })
+            .collect();
+
+        Ok(Self {
             file_path,
-            actions: actions.into(),
-            keymap: ActionToKeycode::new()
+            bindings,
+            dispatcher: keymap.into(),
+            mode: Mode::Browse,
+            row: 0,
+            column: 0,
+            modified: false,
+            recording: vec![],
+            pressed: vec![],
+            recording_at: Instant::now()
+        })
+    }
+
+    /// Binding for the inputs pressed since all inputs were last released,
+    /// long presses are rounded down to half seconds
+    fn recorded_binding(&self) -> Binding {
+        let held = self.recording_at.elapsed().as_millis();
+
+        match self.recording.as_slice() {
+            [input] if held >= LONG_PRESS => Binding::Hold {
+                hold: *input,
+                duration: (held - held % 500) as u32
+            },
+            [input] => Binding::Input(*input),
+            inputs => Binding::Chord { chord: inputs.to_vec() }
+        }
+    }
+
+    fn save_row(&self) -> usize {
+        self.bindings.len()
+    }
+
+    fn quit_row(&self) -> usize {
+        self.bindings.len() + 1
+    }
+
+    /// Bindings of the selected action row
+    fn row_bindings(&mut self) -> Option<&mut Vec<Binding>> {
+        self.bindings.get_mut(self.row)
+            .map(|(_, bindings)| bindings)
+    }
+
+    /// Other actions with the same binding
+    fn conflicts(&self, row: usize, binding: &Binding) -> Vec<&Action> {
+        self.bindings.iter()
+            .enumerate()
+            .filter(|(i, (_, bindings))| *i != row && bindings.contains(binding))
+            .map(|(_, (action, _))| action)
+            .collect()
+    }
+
+    fn conflict_count(&self) -> usize {
+        self.bindings.iter()
+            .enumerate()
+            .flat_map(|(row, (_, bindings))| bindings.iter().map(move |b| (row, b)))
+            .filter(|(row, binding)| !self.conflicts(*row, binding).is_empty())
+            .count()
+    }
+
+    /// Menu action of an input, keyboard arrows, return, backspace and
+    /// escape work even when missing from the keymap being edited
+    fn input_action(&mut self, input: &Input, repeat: bool) -> Option<Action> {
+        if let Some(action) = self.dispatcher.press(*input, repeat) {
+            return Some(action);
+        }
+
+        match input {
+            Input::Key(key) => match SdlKeycode::from_i32(*key)? {
+                SdlKeycode::Up => Some(Action::CursorUp),
+                SdlKeycode::Down => Some(Action::CursorDown),
+                SdlKeycode::Left => Some(Action::PageUp),
+                SdlKeycode::Right => Some(Action::PageDown),
+                SdlKeycode::Return => Some(Action::Select),
+                SdlKeycode::Backspace | SdlKeycode::Escape => Some(Action::Back),
+                _ => None
+            },
+            _ => None
         }
     }
+
+    fn handle_action(&mut self, action: Action) -> Result<EventReply> {
+        match self.mode {
+            Mode::Browse => self.handle_browse_action(action),
+            Mode::ConfirmSave => match action {
+                Action::Select => {
+                    let keymap = self.keymap();
+                    Keymap::save(&keymap, &self.file_path)?;
+                    println!("Saved keymap to {:?}", self.file_path);
+
+                    Ok(EventReply::Exit)
+                },
+                Action::Back => {
+                    self.mode = Mode::Browse;
+                    Ok(EventReply::Handled)
+                },
+                _ => Ok(EventReply::Handled)
+            },
+            Mode::ConfirmQuit => match action {
+                Action::Select => Ok(EventReply::Exit),
+                Action::Back => {
+                    self.mode = Mode::Browse;
+                    Ok(EventReply::Handled)
+                },
+                _ => Ok(EventReply::Handled)
+            },
+            Mode::Record => Ok(EventReply::Handled)
+        }
+    }
+
+    fn handle_browse_action(&mut self, action: Action) -> Result<EventReply> {
+        let column = self.column;
+
+        match action {
+            Action::CursorUp => self.move_row(-1),
+            Action::CursorDown => self.move_row(1),
+            Action::PageUp => self.column = column.saturating_sub(1),
+            Action::PageDown => {
+                let count = self.row_bindings().map(|b| b.len()).unwrap_or(0);
+                self.column = (column + 1).min(count);
+            },
+            Action::Select if self.row == self.save_row() => self.mode = Mode::ConfirmSave,
+            Action::Select if self.row == self.quit_row() => return Ok(self.quit()),
+            Action::Select => {
+                self.recording.clear();
+                self.pressed.clear();
+                self.mode = Mode::Record;
+            },
+            Action::Back => {
+                let removed = match self.row_bindings() {
+                    Some(bindings) if column < bindings.len() => {
+                        bindings.remove(column);
+                        true
+                    },
+                    _ => false
+                };
+
+                if !removed {
+                    return Ok(self.quit());
+                }
+
+                self.modified = true;
+                self.move_row(0);
+            },
+            Action::Favourite => {
+                if let Some(bindings) = self.row_bindings() {
+                    bindings.clear();
+                    self.column = 0;
+                    self.modified = true;
+                }
+            },
+            _ => ()
+        }
+
+        Ok(EventReply::Handled)
+    }
+
+    fn quit(&mut self) -> EventReply {
+        if self.modified {
+            self.mode = Mode::ConfirmQuit;
+            EventReply::Handled
+        } else {
+            EventReply::Exit
+        }
+    }
+
+    /// Move by `inc` rows, keeping the column within the bindings of the row
+    fn move_row(&mut self, inc: i32) {
+        let row = self.row as i32 + inc;
+        self.row = row.clamp(0, self.quit_row() as i32) as usize;
+
+        let count = self.row_bindings().map(|b| b.len()).unwrap_or(0);
+        self.column = self.column.min(count);
+    }
+
+    fn record(&mut self, binding: Binding) {
+        let column = self.column;
+
+        if let Some(bindings) = self.row_bindings() {
+            if column < bindings.len() {
+                bindings[column] = binding;
+            } else if !bindings.contains(&binding) {
+                bindings.push(binding);
+            }
+
+            self.modified = true;
+        }
+
+        self.mode = Mode::Browse;
+        self.move_row(0);
+
+        // releases of the recorded inputs were never seen by the dispatcher
+        self.dispatcher.release_all();
+    }
+
+    fn keymap(&self) -> ActionToBindings {
+        self.bindings.iter()
+            .filter(|(_, bindings)| !bindings.is_empty())
+            .map(|(action, bindings)| {
+                let bindings = match bindings.as_slice() {
+                    [binding] => Bindings::Single(binding.clone()),
+                    bindings => Bindings::Many(bindings.to_vec())
+                };
+                (action.clone(), bindings)
+            })
+            .collect()
+    }
+
+    fn status_text(&self) -> String {
+        match self.mode {
+            Mode::Browse => {
+                let conflicts = self.bindings.get(self.row)
+                    .and_then(|(_, bindings)| bindings.get(self.column))
+                    .map(|binding| self.conflicts(self.row, binding))
+                    .unwrap_or_default();
+
+                if conflicts.is_empty() {
+                    String::from("Select to add or change, back to remove, favourite to clear")
+                } else {
+                    format!("Also bound to {:?}", conflicts)
+                }
+            },
+            Mode::Record => {
+                let (action, _) = &self.bindings[self.row];
+                format!(
+                    "Press input for {:?}, hold for a long press, press together for a combination",
+                    action
+                )
+            },
+            Mode::ConfirmSave => {
+                let conflicts = match self.conflict_count() {
+                    0 => String::new(),
+                    n => format!("{} conflicting bindings. ", n)
+                };
+                format!("{}Save to {}? Select to save, back to cancel", conflicts, self.file_path.display())
+            },
+            Mode::ConfirmQuit => {
+                String::from("Quit without saving? Select to quit, back to cancel")
+            }
+        }
+    }
+
+    fn draw_rows(&self, renderer: &mut Renderer, region: Rect) -> Result<()> {
+        let visible = (region.height() / LINE_HEIGHT).max(1) as usize;
+        let first = (self.row + 1).saturating_sub(visible);
+
+        let mut row_rect = Rect::new(region.x, region.y, region.width(), LINE_HEIGHT);
+
+        for row in (first..=self.quit_row()).take(visible) {
+            let selected = row == self.row;
+
+            match self.bindings.get(row) {
+                Some((action, bindings)) => {
+                    self.draw_action_row(renderer, row_rect, row, action, bindings)?;
+                },
+                None => {
+                    let text = if row == self.save_row() { "Save" } else { "Quit" };
+                    let color = if selected { Color::YELLOW } else { Color::WHITE };
+                    renderer.draw_text(text, color, row_rect, &Justify::Left)?;
+                }
+            }
+
+            row_rect = row_rect.bottom_shifted(LINE_HEIGHT as i32);
+        }
+
+        Ok(())
+    }
+
+    fn draw_action_row(&self,
+        renderer: &mut Renderer,
+        dest: Rect,
+        row: usize,
+        action: &Action,
+        bindings: &[Binding]
+    ) -> Result<()> {
+        let selected = row == self.row;
+
+        let action_color = if selected { Color::YELLOW } else { Color::WHITE };
+        let action_rect = Rect::new(dest.x, dest.y, ACTION_WIDTH, LINE_HEIGHT);
+        renderer.draw_text(format!("{:?}", action), action_color, action_rect, &Justify::Left)?;
+
+        let recording = selected && matches!(self.mode, Mode::Record);
+
+        let mut x = dest.x + ACTION_WIDTH as i32;
+        let right = dest.x + dest.width() as i32;
+
+        let columns = bindings.iter()
+            .map(|b| (b.to_string(), !self.conflicts(row, b).is_empty()))
+            .chain([(String::from("+"), false)]);
+
+        for (column, (text, conflict)) in columns.enumerate() {
+            if x >= right {
+                break;
+            }
+
+            let text = if recording && column == self.column { String::from("...") } else { text };
+
+            let color = if selected && column == self.column {
+                Color::YELLOW
+            } else if conflict {
+                Color::RED
+            } else {
+                Color::GRAY
+            };
+
+            let width = renderer.text_width(&text)?;
+            let rect = Rect::new(x, dest.y, (right - x) as u32, LINE_HEIGHT);
+            renderer.draw_text(&text, color, rect, &Justify::Left)?;
+
+            x += width as i32 + 20;
+        }
+
+        Ok(())
+    }
 }
 
 impl LemonScreen for LemonKeymap {
     fn draw(&self, renderer: &mut Renderer) -> Result<()> {
         renderer.draw_background(Color::BLACK);
 
-        let action = self.actions.front().unwrap();
-        let text = format!("Press key for {:?}", action);
-
         let screen_size = renderer.get_screen_size();
-        let screen_rect = Rect::new(0, 0, screen_size.width, screen_size.height);
-        let dest = Rect::new(0, 0, screen_size.width, 20)
-            .centered_on(screen_rect.center());
+        let width = screen_size.width.saturating_sub(20);
+
+        let title_rect = Rect::new(10, 10, width, LINE_HEIGHT);
+        renderer.draw_text("Keymap", Color::WHITE, title_rect, &Justify::Center)?;
 
-        renderer.draw_text(text, Color::WHITE, dest, &Justify::Center)?;
+        let rows_height = screen_size.height.saturating_sub(LINE_HEIGHT * 4);
+        let rows_rect = Rect::new(10, 10 + 2 * LINE_HEIGHT as i32, width, rows_height);
+        self.draw_rows(renderer, rows_rect)?;
+
+        let status_y = screen_size.height.saturating_sub(LINE_HEIGHT + 10) as i32;
+        let status_rect = Rect::new(10, status_y, width, LINE_HEIGHT);
+        renderer.draw_text(self.status_text(), Color::WHITE, status_rect, &Justify::Center)?;
 
         renderer.present();
 
         Ok(())
     }
 
-    fn handle_keycode(&mut self, _ctx: &mut MainLoopContext, keycode: &Keycode) -> Result<EventReply> {
-        let action = self.actions.pop_front().unwrap();
-        self.keymap.insert(action, (*keycode).into());
+    fn handle_input(&mut self,
+        _ctx: &mut MainLoopContext,
+        input: &Input,
+        repeat: bool
+    ) -> Result<EventReply> {
+        if !matches!(self.mode, Mode::Record) {
+            return match self.input_action(input, repeat) {
+                Some(action) => self.handle_action(action),
+                None => Ok(EventReply::Handled)
+            };
+        }
 
-        if self.actions.is_empty() {
-            Keymap::save(&self.keymap, &self.file_path)?;
-            println!("Saved keymap to {:?}", self.file_path);
+        // a held key shouldn't be recorded twice
+        if repeat || self.pressed.contains(input) {
+            return Ok(EventReply::Handled);
+        }
 
-            Ok(EventReply::Exit)
-        } else {
-            Ok(EventReply::Handled)
+        if self.recording.is_empty() {
+            self.recording_at = Instant::now();
+        }
+
+        if !self.recording.contains(input) {
+            self.recording.push(*input);
         }
+        self.pressed.push(*input);
+
+        Ok(EventReply::Handled)
+    }
+
+    fn handle_input_up(&mut self,
+        _ctx: &mut MainLoopContext,
+        input: &Input
+    ) -> Result<EventReply> {
+        if !matches!(self.mode, Mode::Record) {
+            return match self.dispatcher.release(*input) {
+                Some(action) => self.handle_action(action),
+                None => Ok(EventReply::Handled)
+            };
+        }
+
+        self.pressed.retain(|i| i != input);
+
+        // record once all inputs are released, ignoring the release of the
+        // input that started recording
+        if !self.pressed.is_empty() || self.recording.is_empty() {
+            return Ok(EventReply::Handled);
+        }
+
+        let binding = self.recorded_binding();
+        self.recording.clear();
+        self.record(binding);
+
+        Ok(EventReply::Handled)
+    }
+
+    fn update(&mut self, _ctx: &mut MainLoopContext) -> Result<EventReply> {
+        for action in self.dispatcher.poll() {
+            if let EventReply::Exit = self.handle_action(action)? {
+                return Ok(EventReply::Exit);
+            }
+        }
+
+        Ok(EventReply::Handled)
+    }
+
+    fn redraw_interval(&self) -> Option<u32> {
+        self.dispatcher.next_timeout()
     }
 }
\ No newline at end of file
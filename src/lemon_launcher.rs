@@ -16,76 +16,350 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::{io, path::PathBuf, process::{Command, ExitStatus}};
+use std::{
+    cell::RefCell,
+    collections::{hash_map::Entry, HashMap},
+    io,
+    os::unix::process::CommandExt,
+    path::PathBuf,
+    process::{Child, Command},
+    time::Instant
+};
 
 use anyhow::Result;
-use sdl2::{keyboard::Keycode, rect::Rect};
+use sdl2::{pixels::Color, rect::Rect};
 
 use crate::{
     env::Env,
-    keymap::{Action, SdlKeycodeToAction},
-    lemon_config::{ExecCommand, LemonConfig, ScreenshotWidget, TextWidget, WidgetContent, WidgetField},
+    keymap::{Action, Input, InputDispatcher},
+    launch_log::LaunchLog,
+    lemon_config::{
+        ExecCommand, DEFAULT_EMULATOR, HistoryScroll, HistorySource, HistoryWidget, JumpLetterWidget, Justify,
+        LemonConfig, MouseAxis, ScreenshotWidget, TextWidget, WidgetContent, WidgetField
+    },
     lemon_menu::LemonMenu,
     lemon_screen::{EventReply, LemonScreen},
-    menu_config::MenuEntryAction,
+    lemon_search::{LemonSearch, SearchReply},
+    menu_config::{BuiltInAction, MenuEntryAction, Query},
     renderer::Renderer,
-    rom_library::RomLibrary, MainLoopContext
+    rom_library::{Rom, RomLibrary},
+    supervisor,
+    template::{self, TemplateVars},
+    MainLoopContext
 };
 
 pub struct LemonLauncher {
     pub config: LemonConfig,
     menu: LemonMenu,
-    keymap: SdlKeycodeToAction,
-    env: Env
+    keymap: InputDispatcher,
+    env: Env,
+    selected_text: SelectedText,
+    /// Search screen shown in place of the menu while open
+    search: Option<LemonSearch>,
+    /// Time of the last letter jump, for the jump letter widget
+    jumped_at: Option<Instant>,
+    /// Input being held down and when it was pressed, for cursor acceleration
+    held_input: Option<(Input, Instant)>,
+    /// Mouse motion not yet turned into cursor movement
+    mouse_motion: i32,
+    /// Failed launch shown over the menu until dismissed
+    launch_error: Option<LaunchError>
+}
+
+/// Lines of emulator output shown for a failed launch
+const LAUNCH_ERROR_LINES: usize = 10;
+
+struct LaunchError {
+    message: String,
+    /// Last lines of emulator output
+    output: Vec<String>,
+    log_path: Option<PathBuf>
+}
+
+impl LaunchError {
+    fn new(message: String) -> Self {
+        Self { message, output: vec![], log_path: None }
+    }
+}
+
+/// History widget text of the selected rom, loaded when the selection changes
+struct SelectedText {
+    rom: Option<String>,
+    history: Option<String>,
+    mameinfo: Option<String>,
+    selected_at: Instant,
+    /// Text wrapped by source and widget width, filled in when first drawn
+    wrapped: RefCell<HashMap<(HistorySource, u32), Vec<String>>>
 }
 
 impl LemonLauncher {
-    pub fn new(config: LemonConfig, menu: LemonMenu, keymap: SdlKeycodeToAction) -> Self {
+    pub fn new(config: LemonConfig, menu: LemonMenu, keymap: InputDispatcher) -> Self {
+        let selected_text = SelectedText {
+            rom: None,
+            history: None,
+            mameinfo: None,
+            selected_at: Instant::now(),
+            wrapped: RefCell::default()
+        };
+
         LemonLauncher {
-            config, menu, keymap, env: Env::load()
+            config, menu, keymap, env: Env::load(), selected_text, search: None, jumped_at: None,
+            held_input: None,
+            mouse_motion: 0,
+            launch_error: None
         }
     }
 
+    fn has_history_widget(&self) -> bool {
+        self.config.widgets.iter()
+            .any(|w| matches!(w.content, WidgetContent::History(_)))
+    }
+
+    fn update_selected_text(&mut self) -> Result<()> {
+        let rom = match self.menu.selected().map(|e| &e.action) {
+            Some(MenuEntryAction::Rom { rom, .. }) => Some(rom.clone()),
+            _ => None
+        };
+
+        if rom == self.selected_text.rom {
+            return Ok(());
+        }
+
+        let (history, mameinfo) = match &rom {
+            Some(rom) if self.has_history_widget() => {
+                let rom_lib = RomLibrary::open()?;
+                (rom_lib.get_history(rom)?, rom_lib.get_mameinfo(rom)?)
+            },
+            _ => (None, None)
+        };
+
+        self.selected_text = SelectedText {
+            rom, history, mameinfo, selected_at: Instant::now(), wrapped: RefCell::default()
+        };
+
+        Ok(())
+    }
+
     fn handle_action(&mut self, ctx: &mut MainLoopContext, action: &Action) -> Result<EventReply> {
+        if let Some(search) = &mut self.search {
+            match search.handle_action(action)? {
+                SearchReply::Handled => (),
+                SearchReply::Close => self.search = None,
+                SearchReply::Results(query) => {
+                    self.search = None;
+                    self.menu.open_query(&query)?;
+                }
+            }
+
+            return Ok(EventReply::Handled);
+        }
+
+        if self.launch_error.is_some() {
+            if let Action::Select | Action::Back = action {
+                self.launch_error = None;
+            }
+
+            return Ok(EventReply::Handled);
+        }
+
         let row_count = self.config.menu.get_row_count();
+        let step = self.cursor_step();
+        let wrap = self.config.menu.wrap;
 
         match action {
-            Action::CursorUp => self.menu.move_cursor(-1),
-            Action::PageUp => self.menu.move_cursor(-row_count),
-            Action::CursorDown => self.menu.move_cursor(1),
-            Action::PageDown => self.menu.move_cursor(row_count),
+            Action::CursorUp => self.menu.move_cursor(-step, wrap),
+            Action::PageUp => self.menu.move_cursor(-row_count * step, wrap),
+            Action::CursorDown => self.menu.move_cursor(step, wrap),
+            Action::PageDown => self.menu.move_cursor(row_count * step, wrap),
             Action::Select => return self.handle_select(ctx),
             Action::Back => self.menu.back(),
-            Action::Favourite => self.menu.toggle_favourite()?
+            Action::Favourite => self.menu.toggle_favourite()?,
+            Action::JumpNextLetter => {
+                self.menu.jump(true);
+                self.jumped_at = Some(Instant::now());
+            },
+            Action::JumpPrevLetter => {
+                self.menu.jump(false);
+                self.jumped_at = Some(Instant::now());
+            },
+            Action::Exit => return Ok(EventReply::Exit),
+            // only used while an emulator runs
+            Action::KillEmulator => ()
         }
 
         Ok(EventReply::Handled)
     }
 
+    /// Rows moved per cursor action, speeding up while a key is held
+    fn cursor_step(&self) -> i32 {
+        match (&self.config.menu.acceleration, &self.held_input) {
+            (Some(acceleration), Some((_, pressed_at))) => {
+                acceleration.step(pressed_at.elapsed().as_millis())
+            },
+            _ => 1
+        }
+    }
+
     fn handle_select(&mut self, ctx: &mut MainLoopContext) -> Result<EventReply> {
         if let Some(entry) = self.menu.selected() {
+            let entry = entry.clone();
             let action = entry.action.clone();
             match action {
                 MenuEntryAction::Menu { menu } => {
                     self.menu.open_menu(&menu);
                 },
-                MenuEntryAction::BuiltIn(_) => {
+                MenuEntryAction::BuiltIn(BuiltInAction::Exit) => {
                     return Ok(EventReply::Exit);
                 },
+                MenuEntryAction::BuiltIn(BuiltInAction::Search) => {
+                    self.search = Some(LemonSearch::new(&self.config.menu, self.config.clones));
+                },
                 MenuEntryAction::Query(query) => {
                     self.menu.open_query(&query)?;
                 },
                 MenuEntryAction::Exec(cmd) => {
-                    cmd.exec()?;
+                    let child = cmd.spawn(&TemplateVars::new(&self.env))?;
+                    let config = &self.config.supervisor;
+                    supervisor::supervise(child, ctx, &mut self.keymap, config, config.timeout)?;
+                },
+                MenuEntryAction::Rom { rom, .. }
+                    if entry.details.as_ref().is_some_and(|d| d.has_clones) =>
+                {
+                    self.menu.open_query(&Query::Clones { rom })?;
                 },
-                MenuEntryAction::Rom { rom, .. } => {
+                MenuEntryAction::Rom { rom, params, emulator, pre_launch, post_launch } => {
                     let rom_lib = RomLibrary::open()?;
+                    let detail = rom_lib.get_rom(&rom)?;
+
+                    let emulator = emulator.as_ref()
+                        .or(detail.as_ref().and_then(|d| d.emulator.as_ref()))
+                        .map(|e| e.as_str());
+
+                    // the emulator of a scanned rom may have since been removed
+                    // from config
+                    let command = match self.config.get_emulator(emulator) {
+                        Ok(command) => command,
+                        Err(e) => {
+                            self.launch_error = Some(LaunchError::new(e.to_string()));
+                            return Ok(EventReply::Handled);
+                        }
+                    };
+
                     rom_lib.inc_play_count(&rom)?;
 
-                    // Close window to let mame use the Linux framebuffer
+                    let mut vars = self.rom_vars(&rom, detail.as_ref(), &entry.title);
+                    vars.set("emulator", emulator.unwrap_or(DEFAULT_EMULATOR));
+
+                    // hooks of the menu entry replace those of the emulator or config
+                    let pre_launch = pre_launch.as_deref()
+                        .unwrap_or(self.config.get_pre_launch(emulator));
+                    let post_launch = post_launch.as_deref()
+                        .unwrap_or(self.config.get_post_launch(emulator));
+
+                    let mut args: Vec<&str> = vec![];
+
+                    // point mame at the rom dirs found by the scan of the rom,
+                    // unless the rompath is set in config
+                    let rom_path = match &detail {
+                        Some(detail) => rom_lib.get_rom_path(detail.emulator.as_ref())?
+                            .or(detail.rom_dir.clone()),
+                        None => None
+                    };
+                    let has_rom_path = command.has_arg("-rompath") || command.has_arg("-rp");
+
+                    if let Some(rom_path) = &rom_path {
+                        if self.config.is_mame(emulator) && !has_rom_path {
+                            args.extend(["-rompath", rom_path]);
+                        }
+                    }
+
+                    // emulator args from config come first, followed by params
+                    // from the rom library, then params from the menu entry
+                    let lib_params = rom_lib.get_params(&rom)?;
+                    args.extend(lib_params.iter()
+                        .chain(params.iter())
+                        .flat_map(|p| p.split_whitespace()));
+
+                    // rom name is last unless emulator args place it explicitly
+                    if !command.uses_var("rom") && !command.uses_var("path") {
+                        args.push(&rom);
+                    }
+
+                    // Close window to let emulator use the Linux framebuffer
                     ctx.close_window();
 
-                    self.config.mame.exec_with_args([&rom])?;
+                    // post launch hooks always run, so errors are kept to be
+                    // shown once they're done
+                    let pre_launched = supervisor::run_hooks(
+                        pre_launch, &vars, ctx, &mut self.keymap, &self.config.supervisor
+                    );
+
+                    if let Err(e) = pre_launched {
+                        self.launch_error = Some(
+                            LaunchError::new(format!("Pre launch hooks failed: {}", e))
+                        );
+                    } else {
+                        let mut cmd = command.command_with_args(&vars, args);
+
+                        // the window is already closed, so launch without a log
+                        // when it can't be written and report it afterwards
+                        let log = LaunchLog::create(&self.env, &rom)
+                            .and_then(|mut log| log.attach(&mut cmd).map(|_| log));
+
+                        let log = match log {
+                            Ok(log) => Some(log),
+                            Err(e) => {
+                                self.launch_error = Some(
+                                    LaunchError::new(format!("Unable to write launch log: {}", e))
+                                );
+                                None
+                            }
+                        };
+
+                        let status = cmd.spawn()
+                            .map_err(|e| format!("Unable to run {}: {}", command.cmd, e))
+                            .and_then(|child| {
+                                let config = &self.config.supervisor;
+                                supervisor::supervise(
+                                    child, ctx, &mut self.keymap, config, config.timeout
+                                ).map_err(|e| format!("Unable to supervise {}: {}", rom, e))
+                            });
+
+                        match status {
+                            Ok(status) if !status.success() => {
+                                self.launch_error = Some(LaunchError {
+                                    message: format!("{} failed, {}", rom, status),
+                                    output: log.as_ref()
+                                        .and_then(|l| l.tail(LAUNCH_ERROR_LINES).ok())
+                                        .unwrap_or_default(),
+                                    log_path: log.as_ref().map(|l| l.path().to_path_buf())
+                                });
+                            },
+                            Ok(_) => {},
+                            Err(message) => {
+                                self.launch_error = Some(LaunchError::new(message));
+                            }
+                        }
+                    }
+
+                    let post_launched = supervisor::run_hooks(
+                        post_launch, &vars, ctx, &mut self.keymap, &self.config.supervisor
+                    );
+
+                    if let Err(e) = post_launched {
+                        let message = format!("Post launch hooks failed: {}", e);
+
+                        match &mut self.launch_error {
+                            Some(error) => {
+                                error.message.push('\n');
+                                error.message.push_str(&message);
+                            },
+                            None => self.launch_error = Some(LaunchError::new(message))
+                        }
+                    }
+
+                    // inputs released while the emulator ran were never seen
+                    self.held_input = None;
                 }
             }
         }
@@ -93,6 +367,32 @@ impl LemonLauncher {
         Ok(EventReply::Handled)
     }
 
+    fn rom_vars(&self, rom: &String, detail: Option<&Rom>, title: &String) -> TemplateVars {
+        let mut vars = TemplateVars::new(&self.env);
+        vars.set("rom", rom);
+
+        if let Some(rom_dir) = &self.config.rom_dir {
+            vars.set_path("rom_dir", rom_dir);
+        }
+
+        if let Some(detail) = detail {
+            vars.set("title", &detail.title);
+            vars.set("genre", &detail.category);
+
+            if let Some(path) = &detail.path {
+                vars.set("path", path);
+            }
+
+            if let Some(rom_dir) = &detail.rom_dir {
+                vars.set("rom_dir", rom_dir);
+            }
+        } else {
+            vars.set("title", title);
+        }
+
+        vars
+    }
+
     fn draw_background(&self, renderer: &mut Renderer) -> Result<()> {
         if let Some(background) = &self.config.background {
             if let Some(colour) = background.colour {
@@ -151,6 +451,56 @@ impl LemonLauncher {
         Ok(())
     }
 
+    fn draw_launch_error(&self, renderer: &mut Renderer, error: &LaunchError) -> Result<()> {
+        let screen_size = renderer.get_screen_size();
+        let line_height = self.config.menu.line_height;
+        let text_color = self.config.menu.text_color;
+        let justify = &self.config.menu.justify;
+
+        renderer.fill_rect(Color::BLACK, Rect::new(0, 0, screen_size.width, screen_size.height))?;
+
+        let region = Rect::new(
+            10, 10,
+            screen_size.width.saturating_sub(20),
+            screen_size.height.saturating_sub(20)
+        );
+        let rows = (region.height() / line_height) as usize;
+
+        let mut lines = renderer.wrap_text(&error.message, region.width())?;
+
+        if let Some(log_path) = &error.log_path {
+            lines.extend(renderer.wrap_text(&format!("Log {}", log_path.display()), region.width())?);
+        }
+
+        // output fills the rows left by the message, keeping the last lines
+        let mut output = vec![];
+        for line in &error.output {
+            output.extend(renderer.wrap_text(line, region.width())?);
+        }
+        let skip = (output.len() + lines.len() + 1).saturating_sub(rows);
+
+        let mut row_rect = Rect::new(region.x, region.y, region.width(), line_height);
+
+        for line in &lines {
+            // blank lines can't be rendered
+            if !line.is_empty() {
+                renderer.draw_text(line, Color::RED, row_rect, justify)?;
+            }
+            row_rect = row_rect.bottom_shifted(line_height as i32);
+        }
+
+        row_rect = row_rect.bottom_shifted(line_height as i32);
+
+        for line in output.iter().skip(skip) {
+            if !line.is_empty() {
+                renderer.draw_text(line, text_color, row_rect, &Justify::Left)?;
+            }
+            row_rect = row_rect.bottom_shifted(line_height as i32);
+        }
+
+        Ok(())
+    }
+
     fn draw_widgets(&self, renderer: &mut Renderer) -> Result<()> {
         for widget in &self.config.widgets {
             match &widget.content {
@@ -166,6 +516,12 @@ impl LemonLauncher {
                 },
                 WidgetContent::Screenshot(screenshot) => {
                     self.draw_screenshot_widget(renderer, widget.get_rect(), screenshot)?;
+                },
+                WidgetContent::History(history) => {
+                    self.draw_history_widget(renderer, widget.get_rect(), history)?;
+                },
+                WidgetContent::JumpLetter(jump) => {
+                    self.draw_jump_letter_widget(renderer, widget.get_rect(), jump)?;
                 }
             }
         }
@@ -180,12 +536,19 @@ impl LemonLauncher {
     ) -> Result<()> {
         if let Some(detail) = self.menu.selected_detail() {
             let text = match config.field {
-                WidgetField::Year => detail.year.as_ref(),
-                WidgetField::Manufacturer => detail.manufacturer.as_ref()
+                WidgetField::Year => detail.year.clone(),
+                WidgetField::Manufacturer => detail.manufacturer.clone(),
+                WidgetField::SubCategory => detail.sub_category.clone(),
+                WidgetField::Players => detail.players.clone(),
+                WidgetField::Controls => detail.controls.clone(),
+                WidgetField::Rating => detail.rating.clone(),
+                WidgetField::NumPlayers => detail.num_players.map(|n| n.to_string()),
+                WidgetField::NumButtons => detail.num_buttons.map(|n| n.to_string()),
+                WidgetField::Resolution => detail.resolution.clone()
             };
 
             if let Some(text) = text {
-                let text = config.template.replace("{}", text);
+                let text = config.template.replace("{}", &text);
                 let text_color = config.text_color
                     .unwrap_or(self.config.menu.text_color);
 
@@ -217,6 +580,84 @@ impl LemonLauncher {
         Ok(())
     }
 
+    fn draw_history_widget(&self,
+        renderer: &mut Renderer,
+        dest: Rect,
+        config: &HistoryWidget
+    ) -> Result<()> {
+        let text = match config.source {
+            HistorySource::History => self.selected_text.history.as_ref(),
+            HistorySource::MameInfo => self.selected_text.mameinfo.as_ref()
+        };
+
+        let Some(text) = text else {
+            return Ok(());
+        };
+
+        let line_height = config.line_height
+            .unwrap_or(self.config.menu.line_height);
+        let text_color = config.text_color
+            .unwrap_or(self.config.menu.text_color);
+
+        let mut wrapped = self.selected_text.wrapped.borrow_mut();
+        let lines = match wrapped.entry((config.source, dest.width())) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(renderer.wrap_text(text, dest.width())?)
+        };
+        let visible = (dest.height() / line_height).max(1) as usize;
+
+        // advance by page or line every interval since the rom was selected
+        let steps = self.selected_text.selected_at.elapsed().as_millis() as usize
+            / config.interval.max(1) as usize;
+
+        let offset = if lines.len() <= visible {
+            0
+        } else {
+            match config.scroll {
+                HistoryScroll::Page => (steps % lines.len().div_ceil(visible)) * visible,
+                HistoryScroll::Auto => steps % (lines.len() - visible + 1)
+            }
+        };
+
+        let mut row_rect = Rect::new(dest.x, dest.y, dest.width(), line_height);
+
+        for line in lines.iter().skip(offset).take(visible) {
+            // blank lines can't be rendered
+            if !line.is_empty() {
+                renderer.draw_text(line, text_color, row_rect, &config.justify)?;
+            }
+            row_rect = row_rect.bottom_shifted(line_height as i32);
+        }
+
+        Ok(())
+    }
+
+    fn draw_jump_letter_widget(&self,
+        renderer: &mut Renderer,
+        dest: Rect,
+        config: &JumpLetterWidget
+    ) -> Result<()> {
+        let visible = self.jumped_at
+            .is_some_and(|t| t.elapsed().as_millis() < config.duration as u128);
+
+        if !visible {
+            return Ok(());
+        }
+
+        if let Some(key) = self.menu.selected_jump_key() {
+            if let Some(background) = config.background {
+                renderer.fill_rect(background, dest)?;
+            }
+
+            let text_color = config.text_color
+                .unwrap_or(self.config.menu.text_color);
+
+            renderer.draw_text(key, text_color, dest, &config.justify)?;
+        }
+
+        Ok(())
+    }
+
     fn draw_screenshot_widget(&self,
         renderer: &mut Renderer,
         dest: Rect,
@@ -251,22 +692,120 @@ impl LemonLauncher {
 impl LemonScreen for LemonLauncher {
     fn draw(&self, renderer: &mut Renderer) -> Result<()> {
         self.draw_background(renderer)?;
-        self.draw_menu(renderer)?;
-        self.draw_widgets(renderer)?;
+
+        if let Some(error) = &self.launch_error {
+            // failing to draw the error must not exit the launcher
+            if let Err(e) = self.draw_launch_error(renderer, error) {
+                println!("Unable to draw launch error: {}", e);
+            }
+        } else if let Some(search) = &self.search {
+            search.draw(renderer, &self.config.menu)?;
+        } else {
+            self.draw_menu(renderer)?;
+            self.draw_widgets(renderer)?;
+        }
 
         renderer.present();
 
         Ok(())
     }
 
-    fn handle_keycode(&mut self, ctx: &mut MainLoopContext, keycode: &Keycode) -> Result<EventReply> {
-        if let Some(action) = self.keymap.get(keycode) {
-            // FIXME this clone shouldn't be necessary
-            self.handle_action(ctx, &action.clone())
+    fn handle_input(&mut self,
+        ctx: &mut MainLoopContext,
+        input: &Input,
+        repeat: bool
+    ) -> Result<EventReply> {
+        if !repeat || self.held_input.is_none_or(|(held, _)| held != *input) {
+            self.held_input = Some((*input, Instant::now()));
+        }
+
+        if let Some(action) = self.keymap.press(*input, repeat) {
+            let reply = self.handle_action(ctx, &action)?;
+            self.update_selected_text()?;
+            Ok(reply)
         } else {
             Ok(EventReply::Unhandled)
         }
     }
+
+    fn handle_input_up(&mut self,
+        ctx: &mut MainLoopContext,
+        input: &Input
+    ) -> Result<EventReply> {
+        if self.held_input.is_some_and(|(held, _)| held == *input) {
+            self.held_input = None;
+        }
+
+        if let Some(action) = self.keymap.release(*input) {
+            let reply = self.handle_action(ctx, &action)?;
+            self.update_selected_text()?;
+            Ok(reply)
+        } else {
+            Ok(EventReply::Handled)
+        }
+    }
+
+    fn handle_mouse_motion(&mut self,
+        ctx: &mut MainLoopContext,
+        xrel: i32,
+        yrel: i32
+    ) -> Result<EventReply> {
+        let Some(mouse) = &self.config.mouse else {
+            return Ok(EventReply::Unhandled);
+        };
+
+        let sensitivity = mouse.sensitivity.max(1) as i32;
+
+        self.mouse_motion += match mouse.axis {
+            MouseAxis::X => xrel,
+            MouseAxis::Y => yrel
+        };
+
+        let rows = self.mouse_motion / sensitivity;
+        self.mouse_motion %= sensitivity;
+
+        let action = if rows < 0 { Action::CursorUp } else { Action::CursorDown };
+
+        for _ in 0..rows.abs() {
+            self.handle_action(ctx, &action)?;
+        }
+
+        self.update_selected_text()?;
+
+        Ok(EventReply::Handled)
+    }
+
+    fn update(&mut self, ctx: &mut MainLoopContext) -> Result<EventReply> {
+        // long presses
+        for action in self.keymap.poll() {
+            if let EventReply::Exit = self.handle_action(ctx, &action)? {
+                return Ok(EventReply::Exit);
+            }
+            self.update_selected_text()?;
+        }
+
+        Ok(EventReply::Handled)
+    }
+
+    fn redraw_interval(&self) -> Option<u32> {
+        // only redraw while there is text to scroll
+        let has_text = self.selected_text.history.is_some()
+            || self.selected_text.mameinfo.is_some();
+        let jump_elapsed = self.jumped_at
+            .map(|t| t.elapsed().as_millis() as u32);
+
+        self.config.widgets.iter()
+            .filter_map(|w| match &w.content {
+                WidgetContent::History(history) if has_text => Some(history.interval),
+                // redraw once more to hide the letter
+                WidgetContent::JumpLetter(jump) => jump_elapsed
+                    .filter(|elapsed| *elapsed < jump.duration)
+                    .map(|elapsed| (jump.duration - elapsed).max(1)),
+                _ => None
+            })
+            .chain(self.keymap.next_timeout())
+            .min()
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -284,34 +823,67 @@ impl ConfigError {
 }
 
 impl ExecCommand {
-    fn get_cmd(&self) -> Command {
+    fn get_cmd(&self, vars: &TemplateVars) -> Command {
         let mut cmd = Command::new(&self.cmd);
 
+        // own process group, so processes started by the command are stopped
+        // along with it
+        cmd.process_group(0);
+        supervisor::give_terminal(&mut cmd);
+
         if let Some(env) = &self.env {
-            cmd.envs(env);
+            cmd.envs(env.iter().map(|(k, v)| (k, vars.apply(v))));
         }
 
         if let Some(args) = &self.args {
-            cmd.args(args);
+            cmd.args(args.iter().map(|a| vars.apply(a)));
         }
 
         cmd
     }
 
-    pub fn exec(&self) -> io::Result<ExitStatus> {
-        let mut cmd = self.get_cmd();
-        cmd.spawn()?.wait()
+    /// Check if any of the args is `arg`, e.g. "-rompath"
+    pub fn has_arg(&self, arg: &str) -> bool {
+        self.args.iter()
+            .flatten()
+            .any(|a| a == arg)
+    }
+
+    /// Check if any of the args reference template var `name`
+    pub fn uses_var(&self, name: &str) -> bool {
+        self.args.iter()
+            .flatten()
+            .any(|a| template::has_var(a, name))
     }
 
-    pub fn exec_with_args<I, S>(&self, more_args: I) -> io::Result<ExitStatus>
+    pub fn spawn(&self, vars: &TemplateVars) -> io::Result<Child> {
+        let mut cmd = self.get_cmd(vars);
+        cmd.spawn()
+    }
+
+    /// Spawn with template vars also set as `LEMON_*` environment variables,
+    /// `env` of the command takes precedence
+    pub fn spawn_hook(&self, vars: &TemplateVars) -> io::Result<Child> {
+        let mut cmd = self.get_cmd(vars);
+
+        for (name, value) in vars.env_vars() {
+            if !self.env.as_ref().is_some_and(|env| env.contains_key(&name)) {
+                cmd.env(name, value);
+            }
+        }
+
+        cmd.spawn()
+    }
+
+    pub fn command_with_args<I, S>(&self, vars: &TemplateVars, more_args: I) -> Command
     where
         I: IntoIterator<Item = S>,
         S: AsRef<std::ffi::OsStr>
     {
-        let mut cmd = self.get_cmd();
+        let mut cmd = self.get_cmd(vars);
 
         cmd.args(more_args);
 
-        cmd.spawn()?.wait()
+        cmd
     }
 }
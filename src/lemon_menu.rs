@@ -21,24 +21,30 @@ use std::path::PathBuf;
 use anyhow::Result;
 
 use crate::{
-    menu_config::{MenuConfig, MenuEntry, MenuEntryAction, MenuEntryDetail, Query},
-    rom_library::RomLibrary
+    lemon_config::CloneListing,
+    menu_config::{MenuConfig, MenuEntry, MenuEntryAction, MenuEntryDetail, Query, RomSort},
+    rom_library::{Rom, RomFilter, RomLibrary}
 };
 
 pub struct LemonMenu {
     config: MenuConfig,
+    clones: CloneListing,
     entries: Vec<MenuEntry>,
     index: usize,
-    history: Vec<(Vec<MenuEntry>, usize)>
+    /// Query of the current entries, used to refresh them
+    query: Option<Query>,
+    history: Vec<(Vec<MenuEntry>, usize, Option<Query>)>
 }
 
 impl LemonMenu {
-    pub fn new(config: MenuConfig) -> Self {
+    pub fn new(config: MenuConfig, clones: CloneListing) -> Self {
         let entries = config.main.entries.clone();
         LemonMenu {
             config,
+            clones,
             entries,
             index: 0,
+            query: None,
             history: vec![]
         }
     }
@@ -63,12 +69,12 @@ impl LemonMenu {
 
     pub fn open_menu(&mut self, menu_name: &str) {
         let entries = self.config.menus[menu_name].entries.clone();
-        self.set_entries(entries)
+        self.set_entries(entries, None)
     }
 
     pub fn open_query(&mut self, query: &Query) -> Result<()> {
-        let entries = query.exec()?;
-        self.set_entries(entries);
+        let entries = query.exec(self.clones)?;
+        self.set_entries(entries, Some(query.clone()));
         Ok(())
     }
 
@@ -84,17 +90,16 @@ impl LemonMenu {
         Ok(())
     }
 
-    fn set_entries(&mut self, entries: Vec<MenuEntry>) {
-        self.history.push((self.entries.clone(), self.index));
+    fn set_entries(&mut self, entries: Vec<MenuEntry>, query: Option<Query>) {
+        self.history.push((self.entries.clone(), self.index, self.query.take()));
         self.entries = entries;
         self.index = 0;
+        self.query = query;
     }
 
     fn refresh(&mut self) -> Result<()> {
-        if let Some((entries, index)) = self.history.last() {
-            if let MenuEntryAction::Query(query) = &entries[*index].action {
-                self.entries = query.exec()?;
-            }
+        if let Some(query) = &self.query {
+            self.entries = query.exec(self.clones)?;
         }
 
         Ok(())
@@ -104,13 +109,75 @@ impl LemonMenu {
         if let Some(x) = self.history.pop() {
             self.entries = x.0;
             self.index = x.1;
+            self.query = x.2;
+        }
+    }
+
+    /// Move cursor by `inc` rows, stopping at the first and last entry. With
+    /// `wrap` moving past an end from the first or last entry goes to the
+    /// other end.
+    pub fn move_cursor(&mut self, inc: i32, wrap: bool) {
+        if self.entries.is_empty() {
+            return;
+        }
+
+        let last = self.entries.len() as i32 - 1;
+        let index = self.index as i32;
+        let new_index = index + inc;
+
+        self.index = if new_index < 0 {
+            if wrap && index == 0 { last } else { 0 }
+        } else if new_index > last {
+            if wrap && index == last { 0 } else { last }
+        } else {
+            new_index
+        } as usize;
+    }
+
+    /// Move cursor to the first entry of the next or previous letter, or
+    /// decade in lists sorted by year
+    pub fn jump(&mut self, forward: bool) {
+        let Some(current) = self.selected().map(|e| self.jump_key(e)) else {
+            return
+        };
+
+        let keys: Vec<String> = self.entries.iter()
+            .map(|e| self.jump_key(e))
+            .collect();
+
+        if forward {
+            if let Some(i) = (self.index + 1..keys.len()).find(|i| keys[*i] != current) {
+                self.index = i;
+            }
+        } else if let Some(prev) = (0..self.index).rev().find(|i| keys[*i] != current) {
+            // move to the start of the previous group
+            let start = (0..prev).rev()
+                .take_while(|i| keys[*i] == keys[prev])
+                .last()
+                .unwrap_or(prev);
+            self.index = start;
         }
     }
 
-    pub fn move_cursor(&mut self, inc: i32) {
-        let new_index = self.index as i32 + inc;
-        if new_index >= 0 && new_index < self.entries.len() as i32 {
-            self.index = new_index as usize;
+    /// Letter or decade of the selected entry used by `jump`
+    pub fn selected_jump_key(&self) -> Option<String> {
+        self.selected().map(|e| self.jump_key(e))
+    }
+
+    fn jump_key(&self, entry: &MenuEntry) -> String {
+        let by_year = matches!(self.query, Some(Query::Roms { sort: Some(RomSort::Year), .. }));
+
+        if by_year {
+            let decade = entry.details.as_ref()
+                .and_then(|d| d.year.as_deref())
+                .and_then(decade);
+
+            return decade.unwrap_or(String::from("?"));
+        }
+
+        match entry.title.chars().next() {
+            Some(c) if c.is_alphabetic() => c.to_uppercase().to_string(),
+            _ => String::from("#")
         }
     }
 
@@ -127,8 +194,9 @@ impl LemonMenu {
 }
 
 impl Query {
-    pub fn exec(&self) -> Result<Vec<MenuEntry>> {
+    pub fn exec(&self, clones: CloneListing) -> Result<Vec<MenuEntry>> {
         let rom_lib = RomLibrary::open()?;
+        let include_clones = clones == CloneListing::Inline;
 
         match self {
             Query::Categories => {
@@ -136,36 +204,108 @@ impl Query {
                 let entries = categories.iter()
                     .map(|c| MenuEntry {
                         title: c.clone(),
-                        action: MenuEntryAction::Query(
-                            Query::Roms { genre: Some(c.clone()) }
-                        ),
+                        action: MenuEntryAction::Query(Query::Roms {
+                            genre: Some(c.clone()),
+                            sub_category: None,
+                            players: None,
+                            controls: None,
+                            rating: None,
+                            mature: None,
+                            vertical: None,
+                            sort: None
+                        }),
                         screenshot: None,
                         details: None
                     })
                     .collect();
                 Ok(entries)
             },
-            Query::Roms { genre } => {
-                let roms = rom_lib.list_roms(genre.as_ref())?;
+            Query::Roms {
+                genre, sub_category, players, controls, rating, mature, vertical, sort
+            } => {
+                let roms = rom_lib.list_roms(&RomFilter {
+                    genre: genre.as_ref(),
+                    sub_category: sub_category.as_ref(),
+                    players: players.as_ref(),
+                    controls: controls.as_ref(),
+                    rating: rating.as_ref(),
+                    mature: *mature,
+                    vertical: *vertical,
+                    include_clones,
+                    sort_by_year: *sort == Some(RomSort::Year)
+                })?;
                 let entries = roms.iter()
-                    .map(MenuEntry::from)
+                    .map(|r| rom_entry(r, include_clones))
+                    .collect();
+                Ok(entries)
+            },
+            Query::Clones { rom } => {
+                let roms = rom_lib.list_clones(rom)?;
+                let entries = roms.iter()
+                    .map(|r| rom_entry(r, true))
+                    .collect();
+                Ok(entries)
+            },
+            Query::Search { text } => {
+                let roms = rom_lib.search(text, u32::MAX, include_clones)?;
+                let entries = roms.iter()
+                    .map(|r| rom_entry(r, include_clones))
                     .collect();
                 Ok(entries)
             },
             Query::Favourites { count } => {
                 let roms = rom_lib.list_favourites(*count)?;
                 let entries = roms.iter()
-                    .map(MenuEntry::from)
+                    .map(|r| rom_entry(r, include_clones))
                     .collect();
                 Ok(entries)
             },
             Query::Popular { count } => {
                 let roms = rom_lib.list_most_played(*count)?;
                 let entries = roms.iter()
-                    .map(MenuEntry::from)
+                    .map(|r| rom_entry(r, include_clones))
                     .collect();
                 Ok(entries)
             }
         }
     }
 }
+
+/// Menu entry of rom, which launches the rom directly when clones are listed
+/// already or opens the list of clones otherwise
+fn rom_entry(rom: &Rom, clones_listed: bool) -> MenuEntry {
+    let mut entry = MenuEntry::from(rom);
+
+    if let Some(details) = &mut entry.details {
+        details.has_clones &= !clones_listed;
+    }
+
+    entry
+}
+
+/// Decade of a year such as "1991" or "199?", years from DAT files can be
+/// free-form text
+fn decade(year: &str) -> Option<String> {
+    year.get(..3)
+        .filter(|y| y.chars().all(|c| c.is_ascii_digit()))
+        .map(|y| format!("{}0s", y))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_decade_of_year() {
+        assert_eq!(decade("1991").as_deref(), Some("1990s"));
+        assert_eq!(decade("198?").as_deref(), Some("1980s"));
+        assert_eq!(decade("19").as_deref(), None);
+        assert_eq!(decade("19??").as_deref(), None);
+    }
+
+    #[test]
+    fn skips_non_ascii_years() {
+        assert_eq!(decade("平成3年").as_deref(), None);
+        assert_eq!(decade("19é1").as_deref(), None);
+    }
+}
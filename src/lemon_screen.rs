@@ -17,21 +17,59 @@
  */
 
 use anyhow::Result;
-use sdl2::{event::Event, keyboard::Keycode};
+use sdl2::event::Event;
 
-use crate::{renderer::Renderer, MainLoopContext};
+use crate::{input::InputEvent, keymap::Input, renderer::Renderer, MainLoopContext};
 
 pub trait LemonScreen {
     fn draw(&self, renderer: &mut Renderer) -> Result<()>;
 
-    fn handle_keycode(&mut self, ctx: &mut MainLoopContext, keycode: &Keycode) -> Result<EventReply>;
+    /// Handle a key or controller press, `repeat` is true for repeats while
+    /// the input is held
+    fn handle_input(&mut self,
+        ctx: &mut MainLoopContext,
+        input: &Input,
+        repeat: bool
+    ) -> Result<EventReply>;
+
+    fn handle_input_up(&mut self, _ctx: &mut MainLoopContext, _input: &Input) -> Result<EventReply> {
+        Ok(EventReply::Unhandled)
+    }
+
+    /// Called on every pass of the main loop, for work that is due by time
+    /// rather than by an event
+    fn update(&mut self, _ctx: &mut MainLoopContext) -> Result<EventReply> {
+        Ok(EventReply::Unhandled)
+    }
+
+    fn handle_input_event(&mut self, ctx: &mut MainLoopContext, event: &InputEvent) -> Result<EventReply> {
+        match event {
+            InputEvent::Down { input, repeat } => self.handle_input(ctx, input, *repeat),
+            InputEvent::Up(input) => self.handle_input_up(ctx, input)
+        }
+    }
+
+    /// Milliseconds after which the screen is drawn again when no events
+    /// arrive, `None` to only draw after events
+    fn redraw_interval(&self) -> Option<u32> {
+        None
+    }
+
+    /// Handle relative motion of a mouse, trackball or spinner
+    fn handle_mouse_motion(&mut self,
+        _ctx: &mut MainLoopContext,
+        _xrel: i32,
+        _yrel: i32
+    ) -> Result<EventReply> {
+        Ok(EventReply::Unhandled)
+    }
 
     fn handle_event(&mut self, ctx: &mut MainLoopContext, event: &Event) -> Result<EventReply> {
         match event {
             Event::Quit { .. } => Ok(EventReply::Exit),
-            Event::KeyDown { keycode: Some(keycode), .. } => {
-                self.handle_keycode(ctx, keycode)
-            }
+            Event::MouseMotion { xrel, yrel, .. } => {
+                self.handle_mouse_motion(ctx, *xrel, *yrel)
+            },
             _ => Ok(EventReply::Unhandled)
         }
     }
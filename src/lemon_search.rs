@@ -0,0 +1,185 @@
+/*
+ * Lemon Launcher - SDL based MAME frontend for arcade cabinets
+ * Copyright (C) 2024 Josh Kropf <josh@slashdev.ca>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use anyhow::Result;
+use sdl2::rect::Rect;
+
+use crate::{
+    keymap::Action,
+    lemon_config::{CloneListing, Justify, LemonMenuConfig},
+    menu_config::Query,
+    renderer::Renderer,
+    rom_library::RomLibrary
+};
+
+const COLUMNS: usize = 10;
+
+#[derive(Clone, Copy, PartialEq)]
+enum SearchKey {
+    Char(char),
+    Delete,
+    Done
+}
+
+impl SearchKey {
+    fn label(&self) -> String {
+        match self {
+            SearchKey::Char(' ') => String::from("_"),
+            SearchKey::Char(c) => c.to_string(),
+            SearchKey::Delete => String::from("<"),
+            SearchKey::Done => String::from("OK")
+        }
+    }
+}
+
+pub enum SearchReply {
+    Handled,
+    Close,
+    /// Open search results in the menu
+    Results(Query)
+}
+
+/// Search screen with an on-screen letter picker that only needs the cursor,
+/// page, select and back actions
+pub struct LemonSearch {
+    keys: Vec<SearchKey>,
+    index: usize,
+    text: String,
+    results: Vec<String>,
+    /// Number of results that fit below the search text and letters
+    result_count: u32,
+    clones: CloneListing
+}
+
+impl LemonSearch {
+    pub fn new(config: &LemonMenuConfig, clones: CloneListing) -> Self {
+        let keys: Vec<SearchKey> = ('A'..='Z')
+            .chain('0'..='9')
+            .chain([' '])
+            .map(SearchKey::Char)
+            .chain([SearchKey::Delete, SearchKey::Done])
+            .collect();
+
+        let key_rows = keys.len().div_ceil(COLUMNS) as i32;
+        let result_count = (config.get_row_count() - key_rows - 2).max(0) as u32;
+
+        LemonSearch {
+            keys,
+            index: 0,
+            text: String::new(),
+            results: vec![],
+            result_count,
+            clones
+        }
+    }
+
+    pub fn handle_action(&mut self, action: &Action) -> Result<SearchReply> {
+        match action {
+            Action::CursorUp => self.move_cursor(-(COLUMNS as i32)),
+            Action::CursorDown => self.move_cursor(COLUMNS as i32),
+            Action::PageUp => self.move_cursor(-1),
+            Action::PageDown => self.move_cursor(1),
+            Action::Back => {
+                if self.text.pop().is_none() {
+                    return Ok(SearchReply::Close);
+                }
+                self.update_results()?;
+            },
+            Action::Select => {
+                match self.keys[self.index] {
+                    SearchKey::Char(c) => self.text.push(c),
+                    SearchKey::Delete => {
+                        self.text.pop();
+                    },
+                    SearchKey::Done if self.text.trim().is_empty() => {
+                        return Ok(SearchReply::Close);
+                    },
+                    SearchKey::Done => {
+                        let query = Query::Search { text: self.text.trim().to_string() };
+                        return Ok(SearchReply::Results(query));
+                    }
+                }
+                self.update_results()?;
+            },
+            _ => ()
+        }
+
+        Ok(SearchReply::Handled)
+    }
+
+    fn move_cursor(&mut self, inc: i32) {
+        let new_index = self.index as i32 + inc;
+        if new_index >= 0 && new_index < self.keys.len() as i32 {
+            self.index = new_index as usize;
+        }
+    }
+
+    fn update_results(&mut self) -> Result<()> {
+        let rom_lib = RomLibrary::open()?;
+        let include_clones = self.clones == CloneListing::Inline;
+
+        self.results = rom_lib.search(&self.text, self.result_count, include_clones)?
+            .into_iter()
+            .map(|r| r.title)
+            .collect();
+
+        Ok(())
+    }
+
+    pub fn draw(&self, renderer: &mut Renderer, config: &LemonMenuConfig) -> Result<()> {
+        let region = config.get_rect();
+        let line_height = config.line_height;
+
+        let mut row_rect = Rect::new(region.x, region.y, region.width(), line_height);
+
+        let text = format!("Search: {}_", self.text);
+        renderer.draw_text(text, config.text_color, row_rect, &Justify::Left)?;
+        row_rect = row_rect.bottom_shifted(line_height as i32);
+
+        let key_width = region.width() / COLUMNS as u32;
+
+        for (row, keys) in self.keys.chunks(COLUMNS).enumerate() {
+            for (col, key) in keys.iter().enumerate() {
+                let color = match row * COLUMNS + col == self.index {
+                    true => *config.get_focus_color(),
+                    false => config.text_color
+                };
+
+                let key_rect = Rect::new(
+                    row_rect.x + (col as u32 * key_width) as i32,
+                    row_rect.y,
+                    key_width,
+                    line_height
+                );
+
+                renderer.draw_text(key.label(), color, key_rect, &Justify::Center)?;
+            }
+
+            row_rect = row_rect.bottom_shifted(line_height as i32);
+        }
+
+        row_rect = row_rect.bottom_shifted(line_height as i32);
+
+        for title in &self.results {
+            renderer.draw_text(title, config.text_color, row_rect, &config.justify)?;
+            row_rect = row_rect.bottom_shifted(line_height as i32);
+        }
+
+        Ok(())
+    }
+}
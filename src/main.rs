@@ -17,31 +17,40 @@
  */
 
 mod cli;
+mod dat_xml;
 mod env;
+mod input;
 mod keymap;
+mod launch_log;
 mod lemon_config;
 mod lemon_keymap;
 mod lemon_menu;
 mod lemon_screen;
+mod lemon_search;
 mod lemon_launcher;
 mod mame_xml;
 mod menu_config;
 mod renderer;
 mod rom_library;
 mod scan;
+mod supervisor;
+mod support_files;
+mod template;
 
-use anyhow::{Error, Result};
+use anyhow::{anyhow, Error, Result};
 use cli::{Cli, Commands, Parser};
 
 use env::Env;
+use input::InputEvents;
 use keymap::Keymap;
-use lemon_config::{Font, LemonConfig, Size};
+use lemon_config::{Font, LemonConfig, Size, DEFAULT_EMULATOR};
 use lemon_keymap::LemonKeymap;
 use lemon_launcher::LemonLauncher;
 use lemon_menu::LemonMenu;
 use lemon_screen::{EventReply, LemonScreen};
 use menu_config::MenuConfig;
 use renderer::Renderer;
+use rom_library::RomLibrary;
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
@@ -51,19 +60,54 @@ fn main() -> Result<()> {
     let config = LemonConfig::load_config(&env.get_config_path())?;
 
     match cli.command {
-        Some(Commands::Scan { mame_xml, genre_ini, roms_dir }) => {
-            scan::scan(&mame_xml, &genre_ini, &roms_dir)
+        Some(Commands::Scan {
+            mame_xml, genre_ini, support_files, roms_dirs, recursive, merged, emulator, mut exclude
+        }) => {
+            // fail early when emulator is missing from config
+            config.get_emulator(emulator.as_deref())?;
+
+            exclude.extend(&config.scan.exclude);
+
+            // roms of the mame command are stored without an emulator
+            let emulator = emulator.filter(|e| {
+                e != DEFAULT_EMULATOR || config.emulators.contains_key(e)
+            });
+
+            scan::scan(
+                &mame_xml, &genre_ini, &support_files, &roms_dirs, recursive, merged,
+                emulator.as_ref(), &exclude
+            )
+        },
+        Some(Commands::ScanDir { system, roms_dir, extensions, dat }) => {
+            let emulator = config.emulators.get(&system)
+                .ok_or(anyhow!("Emulator {} not found in config", system))?;
+
+            let extensions = if extensions.is_empty() {
+                &emulator.extensions
+            } else {
+                &extensions
+            };
+
+            if extensions.is_empty() {
+                return Err(anyhow!("No rom file extensions given for {}", system));
+            }
+
+            scan::scan_dir(&roms_dir, &system, extensions, dat.as_deref())
+        },
+        Some(Commands::Params { rom, params }) => {
+            let rom_lib = RomLibrary::open()?;
+            rom_lib.set_params(&rom, params.as_ref())
         },
         Some(Commands::Keymap { file_path }) => {
             let keymap_path = file_path.unwrap_or_else(|| env.get_keymap_path());
 
-            let app = LemonKeymap::new(keymap_path);
+            let app = LemonKeymap::new(keymap_path)?;
 
             main_loop(&config, app)
         },
         None | Some(Commands::Launch) => {
             let menu_config = MenuConfig::load_config(&env.get_menu_path())?;
-            let menu = LemonMenu::new(menu_config);
+            let menu = LemonMenu::new(menu_config, config.clones);
             let keymap = Keymap::load(env.get_keymap_path())?;
 
             let app = LemonLauncher::new(config.clone(), menu, keymap.into());
@@ -74,7 +118,9 @@ fn main() -> Result<()> {
 }
 
 struct MainLoopContext<'ttf> {
-    renderer: Option<Renderer<'ttf>>
+    renderer: Option<Renderer<'ttf>>,
+    event_pump: sdl2::EventPump,
+    inputs: InputEvents
 }
 
 impl<'ttf> MainLoopContext<'ttf> {
@@ -88,7 +134,8 @@ fn new_renderer<'ttf>(
     ttf_context: &'ttf sdl2::ttf::Sdl2TtfContext,
     screen_size: &Size,
     ui_size: &Size,
-    font: &Font
+    font: &Font,
+    relative_mouse: bool
 ) -> Result<Renderer<'ttf>> {
     let font = ttf_context.load_font(&font.get_font_path(), font.size)
         .map_err(|e| Error::msg(e))?;
@@ -103,10 +150,16 @@ fn new_renderer<'ttf>(
 
     sdl_context.mouse().show_cursor(false);
 
+    // report motion of trackballs and spinners past the window edges
+    sdl_context.mouse().set_relative_mouse_mode(relative_mouse);
+
     Renderer::new(font, window, &ui_size)
 }
 
 fn main_loop(config: &LemonConfig, mut app: impl LemonScreen) -> Result<()> {
+    // let the supervisor see joystick inputs while an emulator has focus
+    sdl2::hint::set("SDL_JOYSTICK_ALLOW_BACKGROUND_EVENTS", "1");
+
     let sdl = sdl2::init()
         .map_err(|e| Error::msg(e))?;
 
@@ -115,32 +168,66 @@ fn main_loop(config: &LemonConfig, mut app: impl LemonScreen) -> Result<()> {
 
     let ttf = sdl2::ttf::init()?;
 
-    let mut event_pump = sdl.event_pump()
+    let event_pump = sdl.event_pump()
         .map_err(|e| Error::msg(e))?;
 
+    let inputs = InputEvents::new(&sdl)?;
+
     let mut ctx = MainLoopContext {
         renderer: Some(new_renderer(
             &sdl, &ttf,
             &config.size,
             &config.get_ui_size(),
-            &config.font
-        )?)
+            &config.font,
+            config.mouse.is_some()
+        )?),
+        event_pump,
+        inputs
     };
 
     loop {
-        let event = event_pump.wait_event();
-        match app.handle_event(&mut ctx, &event) {
-            Ok(EventReply::Exit) => break,
-            Err(e) => return Err(e),
-            _ => ()
+        let timeout = [app.redraw_interval(), ctx.inputs.repeat_interval()]
+            .into_iter()
+            .flatten()
+            .min();
+
+        let event = match timeout {
+            Some(timeout) => ctx.event_pump.wait_event_timeout(timeout),
+            None => Some(ctx.event_pump.wait_event())
+        };
+
+        let mut replies = vec![];
+
+        if let Some(event) = event {
+            ctx.inputs.update_devices(&event);
+
+            replies.push(app.handle_event(&mut ctx, &event)?);
+
+            for input_event in ctx.inputs.translate(&event) {
+                replies.push(app.handle_input_event(&mut ctx, &input_event)?);
+            }
+        }
+
+        if let Some(input_event) = ctx.inputs.next_repeat() {
+            replies.push(app.handle_input_event(&mut ctx, &input_event)?);
+        }
+
+        replies.push(app.update(&mut ctx)?);
+
+        if replies.iter().any(|r| matches!(r, EventReply::Exit)) {
+            break;
         }
 
         if ctx.renderer.is_none() {
+            // inputs released while the emulator ran were never seen
+            ctx.inputs.release_all();
+
             let renderer = new_renderer(
                 &sdl, &ttf,
                 &config.size,
                 &config.get_ui_size(),
-                &config.font
+                &config.font,
+                config.mouse.is_some()
             )?;
             ctx.renderer = Some(renderer);
         }
@@ -16,65 +16,31 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::{collections::HashMap, fs::File, io::BufReader, path::Path};
+use std::{collections::HashMap, fs::File, io::{BufRead, BufReader}, path::Path, str::FromStr};
 
-use anyhow::Result;
-use quick_xml::de::from_reader;
-use serde::Deserialize;
+use anyhow::{Error, Result};
+use quick_xml::{events::{BytesStart, Event}, Reader};
 
-#[derive(Deserialize)]
-pub struct Mame {
-    #[serde(rename="machine")]
-    pub machines: Vec<Machine>
-}
+/// Streaming reader of `mame -listxml` output
+pub struct Mame;
 
 impl Mame {
-    pub fn load_xml(file_path: impl AsRef<Path>) -> Result<Self> {
+    /// Load machines for which `keep` returns true given the machine name and
+    /// parent name of clones. Other machines are skipped without being parsed
+    /// to keep memory use down on large xml files.
+    pub fn load_xml_map(
+        file_path: impl AsRef<Path>,
+        keep: impl Fn(&str, Option<&str>) -> bool
+    ) -> Result<HashMap<String, Machine>> {
         let file = File::open(file_path)?;
-        let reader = BufReader::new(file);
-
-        Ok(from_reader(reader)?)
-    }
-
-    pub fn load_xml_map(file_path: impl AsRef<Path>) -> Result<HashMap<String, Machine>> {
-        let root = Self::load_xml(file_path)?;
-        let machine_map = root.machines
-            .into_iter()
-            .map(|m| (m.name.clone(), m))
-            .collect();
-        Ok(machine_map)
+        Self::read_xml_map(BufReader::new(file), keep)
     }
-}
-
-#[allow(dead_code)]
-#[derive(Deserialize)]
-pub struct Machine {
-    #[serde(rename="@name")]
-    pub name: String,
-
-    #[serde(rename="@cloneof")]
-    pub clone_of: Option<String>,
-
-    #[serde(rename="@romof")]
-    pub rom_of: Option<String>,
 
-    #[serde(rename="@ismechanical")]
-    pub is_mechanical: Option<String>,
-
-    #[serde(rename="@isdevice")]
-    pub is_device: Option<String>,
-
-    pub description: String,
-
-    pub year: Option<String>,
-
-    pub manufacturer: Option<String>
-}
-
-/* This is slightly faster than using serde
-impl Machine {
-    pub fn load_xml(xml_path: impl AsRef<Path>) -> Result<HashMap<String, Machine>> {
-        let mut reader = Reader::from_file(xml_path)?;
+    fn read_xml_map<R: BufRead>(
+        reader: R,
+        keep: impl Fn(&str, Option<&str>) -> bool
+    ) -> Result<HashMap<String, Machine>> {
+        let mut reader = Reader::from_reader(reader);
         let mut buf = Vec::new();
 
         let mut result = HashMap::new();
@@ -82,75 +48,318 @@ impl Machine {
         loop {
             match reader.read_event_into(&mut buf)? {
                 Event::Eof => break,
-                Event::Start(element) => {
-                    if element.name().as_ref() == b"machine" {
-                        let machine = Self::from_element(element, &mut reader)?;
+                Event::Start(element) if element.name().as_ref() == b"machine" => {
+                    let element = element.into_owned();
+                    let name = get_attr(&element, &reader, b"name")?
+                        .ok_or(Error::msg("name attr required"))?;
+                    let clone_of = get_attr(&element, &reader, b"cloneof")?;
+
+                    if keep(&name, clone_of.as_deref()) {
+                        let machine = Machine::from_element(name, &element, &mut reader)?;
                         result.insert(machine.name.clone(), machine);
+                    } else {
+                        reader.read_to_end_into(element.name(), &mut Vec::new())?;
                     }
                 },
                 _ => ()
             }
+
+            buf.clear();
         }
 
         Ok(result)
     }
+}
+
+#[allow(dead_code)]
+pub struct Machine {
+    pub name: String,
+    pub clone_of: Option<String>,
+    pub rom_of: Option<String>,
+    pub is_bios: bool,
+    pub is_device: bool,
+    pub is_mechanical: bool,
+    pub is_runnable: bool,
+    pub description: String,
+    pub year: Option<String>,
+    pub manufacturer: Option<String>,
+    /// Emulation status from `<driver status>`, e.g. good, imperfect or preliminary
+    pub driver_status: Option<String>,
+    /// Rotation of the first `<display>` in degrees, 90 and 270 are vertical
+    pub rotate: Option<u32>,
+    pub screen_width: Option<u32>,
+    pub screen_height: Option<u32>,
+    pub refresh: Option<f64>,
+    pub players: Option<u32>,
+    /// Buttons of `<input>`, or the most buttons of its `<control>` elements
+    pub buttons: Option<u32>,
+    pub sound_channels: Option<u32>
+}
 
-    fn from_element(element: BytesStart, reader: &mut Reader<BufReader<File>>) -> Result<Self> {
-        let mut name: Option<String> = None;
-        let mut clone_of: Option<String> = None;
-        let mut rom_of: Option<String> = None;
-        let mut is_mechanical: Option<String> = None;
-        let mut is_device: Option<String> = None;
+impl Machine {
+    fn from_element<R: BufRead>(
+        name: String,
+        element: &BytesStart,
+        reader: &mut Reader<R>
+    ) -> Result<Self> {
+        let clone_of = get_attr(element, reader, b"cloneof")?;
+        let rom_of = get_attr(element, reader, b"romof")?;
+        let is_bios = get_attr(element, reader, b"isbios")?.as_deref() == Some("yes");
+        let is_device = get_attr(element, reader, b"isdevice")?.as_deref() == Some("yes");
+        let is_mechanical = get_attr(element, reader, b"ismechanical")?.as_deref() == Some("yes");
+        let is_runnable = get_attr(element, reader, b"runnable")?.as_deref() != Some("no");
 
         let mut description: Option<String> = None;
         let mut year: Option<String> = None;
         let mut manufacturer: Option<String> = None;
-
-        for attr in element.attributes() {
-            let attr = attr?;
-
-            let val = attr.decode_and_unescape_value(reader.decoder())?;
-
-            match attr.key.as_ref() {
-                b"name" => name = Some(val.to_string()),
-                b"cloneof" => clone_of = Some(val.to_string()),
-                b"romof" => rom_of = Some(val.to_string()),
-                b"ismechanical" => is_mechanical = Some(val.to_string()),
-                b"isdevice" => is_device = Some(val.to_string()),
-                _ => ()
-            }
-        }
-
-        let name = name.ok_or(Error::msg("name attr required"))?;
+        let mut driver_status: Option<String> = None;
+        let mut has_display = false;
+        let mut rotate: Option<u32> = None;
+        let mut screen_width: Option<u32> = None;
+        let mut screen_height: Option<u32> = None;
+        let mut refresh: Option<f64> = None;
+        let mut players: Option<u32> = None;
+        let mut buttons: Option<u32> = None;
+        let mut sound_channels: Option<u32> = None;
 
         let mut buf = Vec::new();
         let mut txt = String::new();
 
         loop {
             match reader.read_event_into(&mut buf)? {
-                Event::Text(e) => txt = e.unescape().unwrap().into_owned(),
+                Event::Start(e) | Event::Empty(e) => {
+                    txt.clear();
+
+                    match e.name().as_ref() {
+                        b"driver" => driver_status = get_attr(&e, reader, b"status")?,
+                        b"display" if !has_display => {
+                            has_display = true;
+                            rotate = parse_attr(&e, reader, b"rotate")?;
+                            screen_width = parse_attr(&e, reader, b"width")?;
+                            screen_height = parse_attr(&e, reader, b"height")?;
+                            refresh = parse_attr(&e, reader, b"refresh")?;
+                        },
+                        b"input" => {
+                            players = parse_attr(&e, reader, b"players")?;
+                            buttons = parse_attr(&e, reader, b"buttons")?;
+                        },
+                        // newer mame versions list buttons per control
+                        b"control" => {
+                            let control_buttons = parse_attr(&e, reader, b"buttons")?;
+                            buttons = buttons.max(control_buttons);
+                        },
+                        b"sound" => sound_channels = parse_attr(&e, reader, b"channels")?,
+                        _ => ()
+                    }
+                },
+                Event::Text(e) => txt = e.unescape()?.into_owned(),
                 Event::End(e) => {
                     match e.name().as_ref() {
                         b"description" => description = Some(txt.clone()),
                         b"manufacturer" => manufacturer = Some(txt.clone()),
                         b"year" => year = Some(txt.clone()),
-                        // This assumes the sub-elements we are interested in
-                        // are always BEFORE other elements
-                        _ => break
+                        b"machine" => break,
+                        _ => ()
                     }
                 },
+                Event::Eof => return Err(Error::msg("unexpected end of machine element")),
                 _ => ()
             }
+
+            buf.clear();
         }
 
         let description = description
             .ok_or(Error::msg("description element required"))?;
 
-        Ok({
-            Machine {
-                name, clone_of, rom_of, is_mechanical, is_device, description,
-                year, manufacturer
-            }
+        Ok(Machine {
+            name, clone_of, rom_of, is_bios, is_device, is_mechanical, is_runnable,
+            description, year, manufacturer, driver_status, rotate, screen_width,
+            screen_height, refresh, players, buttons, sound_channels
         })
     }
-} */
\ No newline at end of file
+}
+
+fn parse_attr<R, T: FromStr>(
+    element: &BytesStart,
+    reader: &Reader<R>,
+    key: &[u8]
+) -> Result<Option<T>> {
+    let val = get_attr(element, reader, key)?;
+    Ok(val.and_then(|v| v.parse().ok()))
+}
+
+fn get_attr<R>(element: &BytesStart, reader: &Reader<R>, key: &[u8]) -> Result<Option<String>> {
+    for attr in element.attributes() {
+        let attr = attr?;
+
+        if attr.key.as_ref() == key {
+            let val = attr.decode_and_unescape_value(reader.decoder())?;
+            return Ok(Some(val.into_owned()));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, io::{self, Read}};
+
+    use super::*;
+
+    const LISTXML: &[u8] = include_bytes!("../tests/fixtures/listxml.xml");
+
+    #[test]
+    fn keeps_only_requested_machines() {
+        let machines = Mame::read_xml_map(LISTXML, |name, _| name == "sf2ua" || name == "dkong")
+            .unwrap();
+
+        assert_eq!(machines.len(), 2);
+
+        let sf2ua = &machines["sf2ua"];
+        assert_eq!(sf2ua.description, "Street Fighter II: The World Warrior (USA 910206)");
+        assert_eq!(sf2ua.clone_of.as_deref(), Some("sf2"));
+        assert_eq!(sf2ua.year.as_deref(), Some("1991"));
+
+        let dkong = &machines["dkong"];
+        assert_eq!(dkong.manufacturer.as_deref(), Some("Nintendo of America"));
+        assert_eq!(dkong.clone_of, None);
+    }
+
+    #[test]
+    fn reads_machine_flags() {
+        let machines = Mame::read_xml_map(LISTXML, |_, _| true).unwrap();
+
+        assert!(machines["neogeo"].is_bios);
+        assert!(machines["z80"].is_device);
+        assert!(!machines["z80"].is_runnable);
+        assert!(machines["3bagflnz"].is_mechanical);
+        assert_eq!(machines["3bagflnz"].driver_status.as_deref(), Some("preliminary"));
+
+        let sf2 = &machines["sf2"];
+        assert!(!sf2.is_bios && !sf2.is_device && !sf2.is_mechanical && sf2.is_runnable);
+        assert_eq!(sf2.driver_status.as_deref(), Some("good"));
+    }
+
+    #[test]
+    fn reads_display_input_and_sound() {
+        let machines = Mame::read_xml_map(LISTXML, |_, _| true).unwrap();
+
+        let sf2 = &machines["sf2"];
+        assert_eq!(sf2.rotate, Some(0));
+        assert_eq!((sf2.screen_width, sf2.screen_height), (Some(384), Some(224)));
+        assert_eq!(sf2.refresh, Some(59.637405));
+        assert_eq!((sf2.players, sf2.buttons), (Some(2), Some(6)));
+        assert_eq!(sf2.sound_channels, Some(1));
+
+        let dkong = &machines["dkong"];
+        assert_eq!(dkong.rotate, Some(270));
+        assert_eq!((dkong.players, dkong.buttons), (Some(2), Some(1)));
+
+        assert_eq!(machines["z80"].rotate, None);
+    }
+
+    #[test]
+    fn keeps_clones_of_requested_parent() {
+        let machines = Mame::read_xml_map(LISTXML, |name, clone_of| {
+            name == "sf2" || clone_of == Some("sf2")
+        }).unwrap();
+
+        assert!(machines.contains_key("sf2"));
+        assert!(machines.contains_key("sf2ua"));
+        assert_eq!(machines.len(), 2);
+    }
+
+    #[test]
+    fn unescapes_text() {
+        let machines = Mame::read_xml_map(LISTXML, |name, _| name == "tom&jerry").unwrap();
+
+        let machine = &machines["tom&jerry"];
+        assert_eq!(machine.description, "Tom & Jerry");
+        assert_eq!(machine.manufacturer.as_deref(), Some("<unknown>"));
+        assert_eq!(machine.year.as_deref(), Some(""));
+    }
+
+    /// Generates `-listxml` output of `count` machines as it's read, so large
+    /// inputs don't need to be held in memory by the test
+    struct GeneratedListXml {
+        count: usize,
+        next: usize,
+        chunk: Vec<u8>,
+        pos: usize
+    }
+
+    impl GeneratedListXml {
+        fn new(count: usize) -> Self {
+            let chunk = b"<?xml version=\"1.0\"?>\n<mame build=\"0.270\">\n".to_vec();
+            Self { count, next: 0, chunk, pos: 0 }
+        }
+
+        fn next_chunk(&mut self) {
+            self.chunk.clear();
+            self.pos = 0;
+
+            if self.next < self.count {
+                let n = self.next;
+                // odd machines are clones of the machine before them
+                let clone_of = if n % 2 == 1 {
+                    format!(" cloneof=\"game{}\"", n - 1)
+                } else {
+                    String::new()
+                };
+                let roms: String = (0..8)
+                    .map(|r| format!("\t\t<rom name=\"game{n}.{r}\" size=\"524288\"/>\n"))
+                    .collect();
+
+                self.chunk.extend(format!(
+                    "\t<machine name=\"game{n}\"{clone_of}>\n\
+                    \t\t<description>Game {n}</description>\n\
+                    \t\t<year>1991</year>\n\
+                    \t\t<manufacturer>Capcom</manufacturer>\n\
+                    {roms}\
+                    \t\t<display type=\"raster\" rotate=\"0\" width=\"384\" height=\"224\"/>\n\
+                    \t\t<input players=\"2\" buttons=\"6\"/>\n\
+                    \t</machine>\n"
+                ).bytes());
+            } else if self.next == self.count {
+                self.chunk.extend(b"</mame>\n");
+            }
+
+            self.next += 1;
+        }
+    }
+
+    impl Read for GeneratedListXml {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.pos == self.chunk.len() {
+                self.next_chunk();
+            }
+
+            let len = buf.len().min(self.chunk.len() - self.pos);
+            buf[..len].copy_from_slice(&self.chunk[self.pos..self.pos + len]);
+            self.pos += len;
+
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn streams_large_xml_keeping_only_requested_machines() {
+        const COUNT: usize = 20_000;
+
+        let seen = Cell::new(0);
+        let reader = io::BufReader::new(GeneratedListXml::new(COUNT));
+
+        let machines = Mame::read_xml_map(reader, |name, _| {
+            seen.set(seen.get() + 1);
+            name == "game10" || name == "game19999"
+        }).unwrap();
+
+        assert_eq!(seen.get(), COUNT);
+        assert_eq!(machines.len(), 2);
+        assert_eq!(machines["game10"].description, "Game 10");
+        assert_eq!(machines["game19999"].clone_of.as_deref(), Some("game19998"));
+        assert_eq!(machines["game19999"].buttons, Some(6));
+    }
+}
\ No newline at end of file
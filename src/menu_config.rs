@@ -60,13 +60,25 @@ impl From<&Rom> for MenuEntry {
             title: r.title.clone(),
             action: MenuEntryAction::Rom {
                 rom: r.name.clone(),
-                params: None
+                params: None,
+                emulator: None,
+                pre_launch: None,
+                post_launch: None
             },
             screenshot: Some(screenshot),
             details: Some(MenuEntryDetail {
                 is_favourite: r.is_favourite,
+                has_clones: r.has_clones,
                 year: r.year.clone(),
-                manufacturer: r.manufacturer.clone()
+                manufacturer: r.manufacturer.clone(),
+                sub_category: r.sub_category.clone(),
+                players: r.players.clone(),
+                controls: r.controls.clone(),
+                rating: r.rating.clone(),
+                num_players: r.num_players,
+                num_buttons: r.num_buttons,
+                resolution: r.screen_width.zip(r.screen_height)
+                    .map(|(w, h)| format!("{}x{}", w, h))
             })
         }
     }
@@ -83,12 +95,19 @@ pub enum MenuEntryAction {
     },
     /// Execute shell command
     Exec(ExecCommand),
-    /// Launch rom using mame
+    /// Launch rom using mame or another emulator
     Rom {
         /// Rom name with file extension (e.g. sf2)
         rom: String,
         /// Optional extra mame arguments
-        params: Option<String>
+        #[serde(alias = "args")]
+        params: Option<String>,
+        /// Optional name of emulator, defaults to rom library emulator or mame
+        emulator: Option<String>,
+        /// Commands run before launching, in place of the emulator or config hooks
+        pre_launch: Option<Vec<ExecCommand>>,
+        /// Commands run after the emulator exits
+        post_launch: Option<Vec<ExecCommand>>
     },
     /// Open menu with entries from rom lib query
     Query(Query)
@@ -97,7 +116,10 @@ pub enum MenuEntryAction {
 #[derive(Deserialize, Clone, PartialEq)]
 pub enum BuiltInAction {
     #[serde(rename="exit")]
-    Exit
+    Exit,
+    /// Open search screen
+    #[serde(rename="search")]
+    Search
 }
 
 #[derive(Deserialize, Clone, PartialEq)]
@@ -107,7 +129,31 @@ pub enum Query {
     Categories,
     #[serde(rename="roms")]
     Roms {
-        genre: Option<String>
+        genre: Option<String>,
+        /// Sub-category from catver.ini
+        sub_category: Option<String>,
+        /// Player count from nplayers.ini, e.g. "2P sim"
+        players: Option<String>,
+        /// Text contained in controls, e.g. "Trackball"
+        controls: Option<String>,
+        /// Rating from bestgames.ini
+        rating: Option<String>,
+        /// Only mature roms when true, no mature roms when false
+        mature: Option<bool>,
+        /// Only vertical roms when true, only horizontal roms when false
+        vertical: Option<bool>,
+        /// Order of roms, by title when not set
+        sort: Option<RomSort>
+    },
+    /// Parent rom followed by its clones
+    #[serde(rename="clones")]
+    Clones {
+        rom: String
+    },
+    /// Roms found by full text search of title, manufacturer and genre
+    #[serde(rename="search")]
+    Search {
+        text: String
     },
     #[serde(rename="favourites")]
     Favourites {
@@ -119,9 +165,27 @@ pub enum Query {
     }
 }
 
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+pub enum RomSort {
+    #[serde(rename="title")]
+    Title,
+    #[serde(rename="year")]
+    Year
+}
+
 #[derive(Deserialize, Clone, PartialEq)]
 pub struct MenuEntryDetail {
     pub is_favourite: bool,
+    /// Selecting the entry lists the clones of the rom instead of launching it
+    pub has_clones: bool,
     pub year: Option<String>,
-    pub manufacturer: Option<String>
+    pub manufacturer: Option<String>,
+    pub sub_category: Option<String>,
+    pub players: Option<String>,
+    pub controls: Option<String>,
+    pub rating: Option<String>,
+    pub num_players: Option<u32>,
+    pub num_buttons: Option<u32>,
+    /// Screen size, e.g. "384x224"
+    pub resolution: Option<String>
 }
@@ -0,0 +1 @@
+ALTER TABLE roms ADD COLUMN emulator TEXT;
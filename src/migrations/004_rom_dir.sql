@@ -0,0 +1 @@
+ALTER TABLE roms ADD COLUMN rom_dir TEXT;
\ No newline at end of file
@@ -0,0 +1,5 @@
+ALTER TABLE roms ADD COLUMN sub_category TEXT;
+ALTER TABLE roms ADD COLUMN mature BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE roms ADD COLUMN players TEXT;
+ALTER TABLE roms ADD COLUMN controls TEXT;
+ALTER TABLE roms ADD COLUMN rating TEXT;
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE roms ADD COLUMN history TEXT;
+ALTER TABLE roms ADD COLUMN mameinfo TEXT;
\ No newline at end of file
@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS roms_clone_of ON roms (clone_of);
\ No newline at end of file
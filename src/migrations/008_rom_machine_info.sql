@@ -0,0 +1,7 @@
+ALTER TABLE roms ADD COLUMN rotate INTEGER;
+ALTER TABLE roms ADD COLUMN screen_width INTEGER;
+ALTER TABLE roms ADD COLUMN screen_height INTEGER;
+ALTER TABLE roms ADD COLUMN refresh REAL;
+ALTER TABLE roms ADD COLUMN num_players INTEGER;
+ALTER TABLE roms ADD COLUMN num_buttons INTEGER;
+ALTER TABLE roms ADD COLUMN sound_channels INTEGER;
\ No newline at end of file
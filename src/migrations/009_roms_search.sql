@@ -0,0 +1,23 @@
+CREATE VIRTUAL TABLE IF NOT EXISTS roms_search USING fts5 (
+   title, manufacturer, genre,
+   content = 'roms', content_rowid = 'rowid'
+);
+
+INSERT INTO roms_search (roms_search) VALUES ('rebuild');
+
+CREATE TRIGGER IF NOT EXISTS roms_search_insert AFTER INSERT ON roms BEGIN
+   INSERT INTO roms_search (rowid, title, manufacturer, genre)
+   VALUES (new.rowid, new.title, new.manufacturer, new.genre);
+END;
+
+CREATE TRIGGER IF NOT EXISTS roms_search_delete AFTER DELETE ON roms BEGIN
+   INSERT INTO roms_search (roms_search, rowid, title, manufacturer, genre)
+   VALUES ('delete', old.rowid, old.title, old.manufacturer, old.genre);
+END;
+
+CREATE TRIGGER IF NOT EXISTS roms_search_update AFTER UPDATE OF title, manufacturer, genre ON roms BEGIN
+   INSERT INTO roms_search (roms_search, rowid, title, manufacturer, genre)
+   VALUES ('delete', old.rowid, old.title, old.manufacturer, old.genre);
+   INSERT INTO roms_search (rowid, title, manufacturer, genre)
+   VALUES (new.rowid, new.title, new.manufacturer, new.genre);
+END;
\ No newline at end of file
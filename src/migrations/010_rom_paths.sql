@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS rom_paths (
+   emulator TEXT UNIQUE,
+   path     TEXT NOT NULL
+);
\ No newline at end of file
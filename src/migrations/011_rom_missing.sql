@@ -0,0 +1 @@
+ALTER TABLE roms ADD COLUMN missing BOOLEAN NOT NULL DEFAULT FALSE;
\ No newline at end of file
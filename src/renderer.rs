@@ -79,6 +79,41 @@ impl<'ttf> Renderer<'ttf> {
             .map_err(|e| Error::msg(e))
     }
 
+    /// Split text into lines that fit in `width`, breaking lines between words
+    pub fn wrap_text(&self, text: &str, width: u32) -> Result<Vec<String>> {
+        let mut lines = vec![];
+
+        for paragraph in text.lines() {
+            let mut line = String::new();
+
+            for word in paragraph.split_whitespace() {
+                let candidate = if line.is_empty() {
+                    word.to_string()
+                } else {
+                    format!("{} {}", line, word)
+                };
+
+                let (candidate_width, _) = self.font.size_of(&candidate)?;
+
+                if candidate_width > width && !line.is_empty() {
+                    lines.push(line);
+                    line = word.to_string();
+                } else {
+                    line = candidate;
+                }
+            }
+
+            lines.push(line);
+        }
+
+        Ok(lines)
+    }
+
+    pub fn text_width(&self, text: &str) -> Result<u32> {
+        let (width, _) = self.font.size_of(text)?;
+        Ok(width)
+    }
+
     pub fn draw_image(&mut self, img_path: &Path, dest: Rect) -> Result<()> {
         let texture_creator = self.canvas.texture_creator();
         let texture = texture_creator.load_texture(img_path)
@@ -92,6 +127,12 @@ impl<'ttf> Renderer<'ttf> {
         self.canvas.clear();
     }
 
+    pub fn fill_rect<C: Into<Color>>(&mut self, color: C, dest: Rect) -> Result<()> {
+        self.canvas.set_draw_color(color);
+        self.canvas.fill_rect(dest)
+            .map_err(Error::msg)
+    }
+
     pub fn draw_background_image(&mut self, img_path: &Path) -> Result<()> {
         let texture_creator = self.canvas.texture_creator();
         let texture = texture_creator.load_texture(img_path)
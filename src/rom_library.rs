@@ -16,13 +16,30 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::path::Path;
+use std::{collections::{HashMap, HashSet}, path::Path};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use fallible_iterator::FallibleIterator;
-use rusqlite::{params, Connection, Params};
+use rusqlite::{params, Connection, OptionalExtension, Params};
 
-use crate::env::Env;
+use crate::{env::Env, lemon_config::DEFAULT_EMULATOR};
+
+/// Schema migrations in the order they are applied. The number of applied
+/// migrations is stored in the database `user_version`, so existing entries
+/// must never be changed or reordered; append a new file instead.
+const MIGRATIONS: &[&str] = &[
+    include_str!("migrations/001_roms_table.sql"),
+    include_str!("migrations/002_rom_emulator.sql"),
+    include_str!("migrations/003_rom_path.sql"),
+    include_str!("migrations/004_rom_dir.sql"),
+    include_str!("migrations/005_rom_info.sql"),
+    include_str!("migrations/006_rom_history.sql"),
+    include_str!("migrations/007_clone_of_index.sql"),
+    include_str!("migrations/008_rom_machine_info.sql"),
+    include_str!("migrations/009_roms_search.sql"),
+    include_str!("migrations/010_rom_paths.sql"),
+    include_str!("migrations/011_rom_missing.sql")
+];
 
 pub struct RomLibrary {
     db: Connection
@@ -33,9 +50,64 @@ pub struct Rom {
     pub title: String,
     pub category: String,
     pub clone_of: Option<String>,
+    /// True when other roms are clones of this rom, not stored by `update_roms`
+    pub has_clones: bool,
     pub is_favourite: bool,
     pub year: Option<String>,
-    pub manufacturer: Option<String>
+    pub manufacturer: Option<String>,
+    pub emulator: Option<String>,
+    pub path: Option<String>,
+    /// Directory the rom set was found in by scan
+    pub rom_dir: Option<String>,
+    /// Sub-category from catver.ini, e.g. "Fighter / Versus"
+    pub sub_category: Option<String>,
+    pub is_mature: bool,
+    /// Player count from nplayers.ini, e.g. "2P sim"
+    pub players: Option<String>,
+    /// Player one controls from controls.ini, e.g. "8-way Joystick"
+    pub controls: Option<String>,
+    /// Rating from bestgames.ini, e.g. "90 to 100 (Best)"
+    pub rating: Option<String>,
+    /// Text from history.dat, only loaded by `get_history`
+    pub history: Option<String>,
+    /// Text from mameinfo.dat, only loaded by `get_mameinfo`
+    pub mameinfo: Option<String>,
+    /// Screen rotation in degrees, 90 and 270 are vertical
+    pub rotate: Option<u32>,
+    pub screen_width: Option<u32>,
+    pub screen_height: Option<u32>,
+    pub refresh: Option<f64>,
+    pub num_players: Option<u32>,
+    pub num_buttons: Option<u32>,
+    pub sound_channels: Option<u32>
+}
+
+/// Optional filters of roms listed by `RomLibrary::list_roms`
+#[derive(Default)]
+pub struct RomFilter<'a> {
+    pub genre: Option<&'a String>,
+    pub sub_category: Option<&'a String>,
+    pub players: Option<&'a String>,
+    /// Roms with controls containing this text
+    pub controls: Option<&'a String>,
+    pub rating: Option<&'a String>,
+    pub mature: Option<bool>,
+    /// Only vertical roms when true, only horizontal roms when false
+    pub vertical: Option<bool>,
+    /// List clones along with parent roms
+    pub include_clones: bool,
+    /// Order by year then title, by title otherwise
+    pub sort_by_year: bool
+}
+
+#[derive(Default)]
+pub struct UpdateSummary {
+    pub added: usize,
+    pub updated: usize,
+    /// Roms no longer found, kept but hidden until found again
+    pub missing: usize,
+    /// Roms already in the library for another emulator
+    pub skipped: usize
 }
 
 impl RomLibrary {
@@ -45,27 +117,19 @@ impl RomLibrary {
     }
 
     pub fn open_file(db_file: impl AsRef<Path>) -> Result<Self> {
-        let db = Connection::open(db_file)?;
+        let mut db = Connection::open(db_file)?;
 
-        db.execute(include_str!("roms_table.sql"), ())?;
+        migrate(&mut db)?;
 
         Ok(RomLibrary { db })
     }
 
-    pub fn clear(&self) -> Result<()> {
-        self.db.execute("delete from roms", ())?;
-        Ok(())
-    }
-
-    // pub fn add_rom(&self, rom: &Rom) -> Result<()> {
-    //     self.db.execute(
-    //         "insert into roms (name, title, genre, clone_of) values (?1, ?2, ?3, ?4)",
-    //         params![rom.name, rom.title, rom.category, rom.clone_of]
-    //     )?;
-    //     Ok(())
-    // }
-
-    pub fn add_roms(&self, roms: &Vec<&Rom>) -> Result<()> {
+    /// Insert new roms, update metadata of existing roms and mark roms of
+    /// `emulator` that are no longer present as missing. Missing roms are left
+    /// out of queries until found again, so an unmounted rom dir doesn't lose
+    /// play counts, favourites and params. Roms are stored by name, so roms
+    /// already stored for another emulator are skipped.
+    pub fn update_roms(&self, roms: &[Rom], emulator: Option<&String>) -> Result<UpdateSummary> {
         // this magic makes batch insert take seconds vs dozens of minutes
         // https://github.com/avinassh/fast-sqlite3-inserts/blob/cbe53fd/src/bin/basic_prep.rs
         self.db.execute_batch("
@@ -76,23 +140,111 @@ impl RomLibrary {
             PRAGMA temp_store = MEMORY;
         ")?;
 
-        let mut stmt = self.db.prepare("
-            insert into roms (name, title, genre, clone_of, year, manufacturer)
-            values (?1, ?2, ?3, ?4, ?5, ?6)
-        ")?;
+        let tx = self.db.unchecked_transaction()?;
+
+        let existing: HashMap<String, Option<String>> = tx.prepare("select name, emulator from roms")?
+            .query([])?
+            .map(|r| Ok((r.get(0)?, r.get(1)?)))
+            .collect()?;
 
-        for rom in roms {
-            stmt.execute(params![
-                rom.name,
-                rom.title,
-                rom.category,
-                rom.clone_of,
-                rom.year,
-                rom.manufacturer
-            ])?;
+        let mut summary = UpdateSummary::default();
+
+        {
+            let mut insert_stmt = tx.prepare("
+                insert into roms (
+                    name, title, genre, clone_of, year, manufacturer, emulator, path, rom_dir,
+                    sub_category, mature, players, controls, rating, history, mameinfo,
+                    rotate, screen_width, screen_height, refresh, num_players, num_buttons,
+                    sound_channels
+                )
+                values (
+                    ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16,
+                    ?17, ?18, ?19, ?20, ?21, ?22, ?23
+                )
+            ")?;
+
+            // only touch rows where the metadata actually changed so that the
+            // number of changes can be reported back
+            let mut update_stmt = tx.prepare("
+                update roms
+                set title = ?2, genre = ?3, clone_of = ?4, year = ?5, manufacturer = ?6,
+                    emulator = ?7, path = ?8, rom_dir = ?9, sub_category = ?10, mature = ?11,
+                    players = ?12, controls = ?13, rating = ?14, history = ?15, mameinfo = ?16,
+                    rotate = ?17, screen_width = ?18, screen_height = ?19, refresh = ?20,
+                    num_players = ?21, num_buttons = ?22, sound_channels = ?23, missing = FALSE
+                where name = ?1 and (
+                    missing or title is not ?2 or genre is not ?3 or clone_of is not ?4
+                    or year is not ?5 or manufacturer is not ?6 or emulator is not ?7
+                    or path is not ?8 or rom_dir is not ?9 or sub_category is not ?10
+                    or mature is not ?11 or players is not ?12 or controls is not ?13
+                    or rating is not ?14 or history is not ?15 or mameinfo is not ?16
+                    or rotate is not ?17 or screen_width is not ?18 or screen_height is not ?19
+                    or refresh is not ?20 or num_players is not ?21 or num_buttons is not ?22
+                    or sound_channels is not ?23
+                )
+            ")?;
+
+            let mut missing_stmt = tx.prepare(
+                "update roms set missing = TRUE where name = ?1 and not missing"
+            )?;
+
+            for rom in roms {
+                let rom_params = params![
+                    rom.name,
+                    rom.title,
+                    rom.category,
+                    rom.clone_of,
+                    rom.year,
+                    rom.manufacturer,
+                    rom.emulator,
+                    rom.path,
+                    rom.rom_dir,
+                    rom.sub_category,
+                    rom.is_mature,
+                    rom.players,
+                    rom.controls,
+                    rom.rating,
+                    rom.history,
+                    rom.mameinfo,
+                    rom.rotate,
+                    rom.screen_width,
+                    rom.screen_height,
+                    rom.refresh,
+                    rom.num_players,
+                    rom.num_buttons,
+                    rom.sound_channels
+                ];
+
+                match existing.get(&rom.name) {
+                    Some(rom_emulator) if rom_emulator != &rom.emulator => {
+                        println!(
+                            "Skipping rom {}, already in library for emulator {}",
+                            rom.name, rom_emulator.as_deref().unwrap_or(DEFAULT_EMULATOR)
+                        );
+                        summary.skipped += 1;
+                    },
+                    Some(_) => summary.updated += update_stmt.execute(rom_params)?,
+                    None => summary.added += insert_stmt.execute(rom_params)?
+                }
+            }
+
+            let scanned: HashSet<&String> = roms.iter()
+                .map(|r| &r.name)
+                .collect();
+
+            let missing = existing.iter()
+                .filter(|(name, rom_emulator)| {
+                    rom_emulator.as_ref() == emulator && !scanned.contains(name)
+                });
+
+            for (name, _) in missing {
+                summary.missing += missing_stmt.execute([name])?;
+            }
         }
 
-        Ok(())
+        tx.commit()?;
+
+        Ok(summary)
     }
 
     pub fn inc_play_count(&self, rom_name: &String) -> Result<()> {
@@ -111,9 +263,68 @@ impl RomLibrary {
         Ok(())
     }
 
+    pub fn get_params(&self, rom_name: &String) -> Result<Option<String>> {
+        let params = self.db.query_row(
+            "select params from roms where name = ?1",
+            [rom_name],
+            |r| r.get(0)
+        ).optional()?;
+        Ok(params.flatten())
+    }
+
+    pub fn set_params(&self, rom_name: &String, params: Option<&String>) -> Result<()> {
+        let count = self.db.execute("
+            update roms set params = ?2
+            where name = ?1
+        ", params![rom_name, params])?;
+
+        if count == 0 {
+            return Err(anyhow!("Rom {} not found in library", rom_name));
+        }
+
+        Ok(())
+    }
+
+    /// Store the mame rom path of the dirs `emulator` found rom sets in
+    pub fn set_rom_path(&self, emulator: Option<&String>, path: &str) -> Result<()> {
+        self.db.execute("delete from rom_paths where emulator is ?1", [emulator])?;
+        self.db.execute(
+            "insert into rom_paths (emulator, path) values (?1, ?2)",
+            params![emulator, path]
+        )?;
+        Ok(())
+    }
+
+    pub fn get_rom_path(&self, emulator: Option<&String>) -> Result<Option<String>> {
+        let path = self.db.query_row(
+            "select path from rom_paths where emulator is ?1",
+            [emulator],
+            |r| r.get(0)
+        ).optional()?;
+        Ok(path)
+    }
+
+    pub fn get_history(&self, rom_name: &String) -> Result<Option<String>> {
+        let history = self.db.query_row(
+            "select history from roms where name = ?1",
+            [rom_name],
+            |r| r.get(0)
+        ).optional()?;
+        Ok(history.flatten())
+    }
+
+    pub fn get_mameinfo(&self, rom_name: &String) -> Result<Option<String>> {
+        let mameinfo = self.db.query_row(
+            "select mameinfo from roms where name = ?1",
+            [rom_name],
+            |r| r.get(0)
+        ).optional()?;
+        Ok(mameinfo.flatten())
+    }
+
     pub fn list_categories(&self) -> Result<Vec<String>> {
         let mut stmt = self.db.prepare(
-            "select genre from roms where clone_of is null group by genre"
+            "select genre from roms where clone_of is null and not missing group by genre"
         )?;
         let rows = stmt.query([])?;
         let categories = rows
@@ -124,7 +335,12 @@ impl RomLibrary {
 
     fn roms_query<P: Params>(&self, sql: &str, params: P) -> Result<Vec<Rom>> {
         let sql = format!(
-            "select name, title, genre, favourite, year, manufacturer from roms {}",
+            "select name, title, genre, favourite, year, manufacturer, emulator, path, rom_dir,
+                sub_category, mature, players, controls, rating, clone_of,
+                exists(select 1 from roms c where c.clone_of = roms.name and not c.missing),
+                rotate, screen_width, screen_height, refresh, num_players, num_buttons,
+                sound_channels
+            from (select rowid, * from roms where not missing) as roms {}",
             sql
         );
 
@@ -136,26 +352,94 @@ impl RomLibrary {
                 name: r.get(0)?,
                 title: r.get(1)?,
                 category: r.get(2)?,
-                clone_of: None,
+                clone_of: r.get(14)?,
+                has_clones: r.get(15)?,
                 is_favourite: r.get(3)?,
                 year: r.get(4)?,
-                manufacturer: r.get(5)?
+                manufacturer: r.get(5)?,
+                emulator: r.get(6)?,
+                path: r.get(7)?,
+                rom_dir: r.get(8)?,
+                sub_category: r.get(9)?,
+                is_mature: r.get(10)?,
+                players: r.get(11)?,
+                controls: r.get(12)?,
+                rating: r.get(13)?,
+                history: None,
+                mameinfo: None,
+                rotate: r.get(16)?,
+                screen_width: r.get(17)?,
+                screen_height: r.get(18)?,
+                refresh: r.get(19)?,
+                num_players: r.get(20)?,
+                num_buttons: r.get(21)?,
+                sound_channels: r.get(22)?
             }))
             .collect()?;
 
         Ok(roms)
     }
 
-    pub fn list_roms(&self, category: Option<&String>) -> Result<Vec<Rom>> {
+    pub fn get_rom(&self, rom_name: &String) -> Result<Option<Rom>> {
+        let roms = self.roms_query("where name = ?1", [rom_name])?;
+        Ok(roms.into_iter().next())
+    }
+
+    pub fn list_roms(&self, filter: &RomFilter) -> Result<Vec<Rom>> {
         self.roms_query("
-            where clone_of is null and (?1 is null or genre = ?1)
+            where (?7 or clone_of is null)
+                and (?1 is null or genre = ?1)
+                and (?2 is null or sub_category = ?2)
+                and (?3 is null or players = ?3)
+                and (?4 is null or controls like '%' || ?4 || '%')
+                and (?5 is null or rating = ?5)
+                and (?6 is null or mature = ?6)
+                and (?8 is null or (rotate in (90, 270)) = ?8)
+            order by case when ?9 then year end, title
+        ", params![
+            filter.genre,
+            filter.sub_category,
+            filter.players,
+            filter.controls,
+            filter.rating,
+            filter.mature,
+            filter.include_clones,
+            filter.vertical,
+            filter.sort_by_year
+        ])
+    }
+
+    /// Full text search of title, manufacturer and genre. Each word of `text`
+    /// matches the start of a word, e.g. "str fig" finds "Street Fighter".
+    pub fn search(&self, text: &str, limit: u32, include_clones: bool) -> Result<Vec<Rom>> {
+        let query = text.split_whitespace()
+            .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        if query.is_empty() {
+            return Ok(vec![]);
+        }
+
+        self.roms_query("
+            where rowid in (select rowid from roms_search where roms_search match ?1)
+                and (?3 or clone_of is null)
             order by title
-        ", [category])
+            limit ?2
+        ", params![query, limit, include_clones])
+    }
+
+    /// List parent rom followed by its clones
+    pub fn list_clones(&self, parent: &String) -> Result<Vec<Rom>> {
+        self.roms_query("
+            where name = ?1 or clone_of = ?1
+            order by name = ?1 desc, title
+        ", [parent])
     }
 
     pub fn list_favourites(&self, count: u32) -> Result<Vec<Rom>> {
         self.roms_query("
-            where clone_of is null and favourite = 1
+            where favourite = 1
             order by title
             limit ?1
         ", [count])
@@ -163,9 +447,173 @@ impl RomLibrary {
 
     pub fn list_most_played(&self, count: u32) -> Result<Vec<Rom>> {
         self.roms_query("
-            where clone_of is null and play_count > 0
+            where play_count > 0
             order by play_count desc, title
             limit ?1
         ", [count])
     }
-}
\ No newline at end of file
+}
+
+fn migrate(db: &mut Connection) -> Result<()> {
+    let version: usize = db.pragma_query_value(None, "user_version", |r| r.get(0))?;
+
+    if version > MIGRATIONS.len() {
+        return Err(anyhow!(
+            "Rom library schema version {} is newer than supported version {}",
+            version, MIGRATIONS.len()
+        ));
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = db.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", index + 1)?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn library() -> RomLibrary {
+        RomLibrary::open_file(":memory:").unwrap()
+    }
+
+    fn rom(name: &str, title: &str, emulator: Option<&str>) -> Rom {
+        Rom {
+            name: name.to_string(),
+            title: title.to_string(),
+            category: "Fighter".to_string(),
+            clone_of: None,
+            has_clones: false,
+            is_favourite: false,
+            year: Some("1991".to_string()),
+            manufacturer: Some("Capcom".to_string()),
+            emulator: emulator.map(String::from),
+            path: None,
+            rom_dir: None,
+            sub_category: None,
+            is_mature: false,
+            players: None,
+            controls: None,
+            rating: None,
+            history: None,
+            mameinfo: None,
+            rotate: None,
+            screen_width: None,
+            screen_height: None,
+            refresh: None,
+            num_players: None,
+            num_buttons: None,
+            sound_channels: None
+        }
+    }
+
+    fn names(roms: Vec<Rom>) -> Vec<String> {
+        roms.into_iter().map(|r| r.name).collect()
+    }
+
+    #[test]
+    fn migrates_legacy_schema_keeping_favourites() {
+        let mut db = Connection::open_in_memory().unwrap();
+        db.execute_batch(MIGRATIONS[0]).unwrap();
+        db.execute("
+            insert into roms (name, title, genre, play_count, favourite)
+            values ('sf2', 'Street Fighter II', 'Fighter', 3, TRUE)
+        ", []).unwrap();
+
+        migrate(&mut db).unwrap();
+
+        let version: usize = db.pragma_query_value(None, "user_version", |r| r.get(0)).unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+
+        let rom_lib = RomLibrary { db };
+        assert_eq!(names(rom_lib.list_favourites(10).unwrap()), ["sf2"]);
+        assert_eq!(names(rom_lib.list_most_played(10).unwrap()), ["sf2"]);
+        assert_eq!(names(rom_lib.search("street", 10, false).unwrap()), ["sf2"]);
+    }
+
+    #[test]
+    fn rescan_keeps_favourites_and_play_counts() {
+        let rom_lib = library();
+        let roms = [rom("sf2", "Street Fighter II", None), rom("dkong", "Donkey Kong", None)];
+        rom_lib.update_roms(&roms, None).unwrap();
+
+        rom_lib.toggle_favourite(&"sf2".to_string()).unwrap();
+        rom_lib.inc_play_count(&"sf2".to_string()).unwrap();
+
+        let roms = [
+            rom("sf2", "Street Fighter II: The World Warrior", None),
+            rom("dkong", "Donkey Kong", None)
+        ];
+        let summary = rom_lib.update_roms(&roms, None).unwrap();
+        assert_eq!((summary.added, summary.updated, summary.missing), (0, 1, 0));
+
+        let sf2 = rom_lib.get_rom(&"sf2".to_string()).unwrap().unwrap();
+        assert_eq!(sf2.title, "Street Fighter II: The World Warrior");
+        assert!(sf2.is_favourite);
+        assert_eq!(names(rom_lib.list_most_played(10).unwrap()), ["sf2"]);
+    }
+
+    #[test]
+    fn hides_missing_roms_until_found_again() {
+        let rom_lib = library();
+        let roms = [rom("sf2", "Street Fighter II", None), rom("dkong", "Donkey Kong", None)];
+        rom_lib.update_roms(&roms, None).unwrap();
+        rom_lib.toggle_favourite(&"dkong".to_string()).unwrap();
+
+        // rom dir unmounted
+        let summary = rom_lib.update_roms(&[], None).unwrap();
+        assert_eq!(summary.missing, 2);
+        assert!(rom_lib.get_rom(&"dkong".to_string()).unwrap().is_none());
+        assert!(rom_lib.list_favourites(10).unwrap().is_empty());
+        assert!(rom_lib.list_categories().unwrap().is_empty());
+
+        let summary = rom_lib.update_roms(&roms, None).unwrap();
+        assert_eq!((summary.added, summary.updated), (0, 2));
+        assert_eq!(names(rom_lib.list_favourites(10).unwrap()), ["dkong"]);
+    }
+
+    #[test]
+    fn leaves_roms_of_other_emulators_alone() {
+        let rom_lib = library();
+        rom_lib.update_roms(&[rom("sf2", "Street Fighter II", None)], None).unwrap();
+
+        let emulator = Some("mame_next".to_string());
+        let roms = [
+            rom("sf2", "Street Fighter II (next)", Some("mame_next")),
+            rom("mk", "Mortal Kombat", Some("mame_next"))
+        ];
+        let summary = rom_lib.update_roms(&roms, emulator.as_ref()).unwrap();
+        assert_eq!((summary.added, summary.skipped), (1, 1));
+
+        let sf2 = rom_lib.get_rom(&"sf2".to_string()).unwrap().unwrap();
+        assert_eq!(sf2.title, "Street Fighter II");
+        assert_eq!(sf2.emulator, None);
+
+        let summary = rom_lib.update_roms(&[], emulator.as_ref()).unwrap();
+        assert_eq!(summary.missing, 1);
+        assert!(rom_lib.get_rom(&"sf2".to_string()).unwrap().is_some());
+        assert!(rom_lib.get_rom(&"mk".to_string()).unwrap().is_none());
+    }
+
+    #[test]
+    fn search_follows_inserts_updates_and_deletes() {
+        let rom_lib = library();
+        rom_lib.update_roms(&[rom("sf2", "Street Fighter II", None)], None).unwrap();
+
+        assert_eq!(names(rom_lib.search("stre", 10, false).unwrap()), ["sf2"]);
+        assert_eq!(names(rom_lib.search("capc", 10, false).unwrap()), ["sf2"]);
+
+        rom_lib.update_roms(&[rom("sf2", "World Warrior", None)], None).unwrap();
+        assert!(rom_lib.search("stre", 10, false).unwrap().is_empty());
+        assert_eq!(names(rom_lib.search("wor", 10, false).unwrap()), ["sf2"]);
+
+        rom_lib.db.execute("delete from roms where name = 'sf2'", []).unwrap();
+        assert!(rom_lib.search("wor", 10, false).unwrap().is_empty());
+        assert!(rom_lib.search("capc", 10, false).unwrap().is_empty());
+    }
+}
@@ -16,77 +16,417 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::{collections::HashMap, fs, path::Path};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet}, fs::{self, File}, io::Read, path::{Path, PathBuf}
+};
 
 use anyhow::{Error, Result};
+use clap::ValueEnum;
 use configparser::ini::Ini;
+use serde::Deserialize;
 
 use crate::{
-    mame_xml::Mame,
-    rom_library::{Rom, RomLibrary}
+    dat_xml::DatIndex,
+    mame_xml::{Machine, Mame},
+    rom_library::{Rom, RomLibrary},
+    support_files::SupportFiles
 };
 
-pub fn scan(mame_xml: &Path, genre_ini: &Path, roms_dir: &Path) -> Result<()> {
-    let mame_db = Mame::load_xml_map(mame_xml)?;
+/// Kinds of machines left out of the library by `scan`
+#[derive(Deserialize, ValueEnum, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Exclude {
+    /// BIOS sets
+    Bios,
+    /// Devices, e.g. cpus and sound chips
+    Device,
+    /// Mechanical machines such as pinball and slot machines
+    Mechanical,
+    /// Machines that can't be run on their own
+    NotRunnable,
+    /// Drivers with status "preliminary"
+    Preliminary
+}
+
+impl Exclude {
+    pub fn matches(&self, machine: &Machine) -> bool {
+        match self {
+            Exclude::Bios => machine.is_bios,
+            Exclude::Device => machine.is_device,
+            Exclude::Mechanical => machine.is_mechanical,
+            Exclude::NotRunnable => !machine.is_runnable,
+            Exclude::Preliminary => machine.driver_status.as_deref() == Some("preliminary")
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn scan(
+    mame_xml: &Path,
+    genre_ini: &Path,
+    support_files: &SupportFiles,
+    roms_dirs: &[PathBuf],
+    recursive: bool,
+    merged: bool,
+    emulator: Option<&String>,
+    exclude: &[Exclude]
+) -> Result<()> {
+    let mut rom_files = HashMap::new();
+
+    for roms_dir in roms_dirs {
+        find_rom_sets(roms_dir, recursive, &mut rom_files)?;
+    }
+
+    // only keep machines of the rom dir, full -listxml is several hundred MB
+    let mame_db = Mame::load_xml_map(mame_xml, |name, clone_of| {
+        rom_set_name(name, clone_of, &rom_files, merged).is_some()
+    })?;
     let categories = parse_genre_ini(genre_ini)?;
+    let mut rom_info = support_files.load()?;
 
-    let mut rom_meta: HashMap<&String, Rom> = HashMap::new();
+
+    let mut roms = vec![];
+    let mut found = HashSet::new();
 
     let category_map = categories.get_map_ref();
     for (category, category_roms) in category_map {
         for (rom, _) in category_roms {
-            if let Some(machine) = mame_db.get(rom) {
-                rom_meta.insert(rom, Rom {
+            let Some(machine) = mame_db.get(rom) else {
+                continue
+            };
+
+            let clone_of = machine.clone_of.as_deref();
+            let Some(set_name) = rom_set_name(rom, clone_of, &rom_files, merged) else {
+                continue
+            };
+
+            found.insert(set_name.to_string());
+            let rom_file = &rom_files[set_name];
+
+            if let Some(reason) = exclude.iter().find(|e| e.matches(machine)) {
+                println!("Excluding rom {rom} ({:?})", reason);
+            } else {
+                let info = rom_info.remove(rom).unwrap_or_default();
+
+                roms.push(Rom {
                     name: rom.clone(),
                     title: machine.description.clone(),
                     category: category.clone(),
                     clone_of: machine.clone_of.clone(),
+                    has_clones: false,
                     is_favourite: false,
                     year: machine.year.clone(),
-                    manufacturer: machine.manufacturer.clone()
+                    manufacturer: machine.manufacturer.clone(),
+                    emulator: emulator.cloned(),
+                    path: Some(rom_file.to_string_lossy().to_string()),
+                    rom_dir: rom_file.parent()
+                        .map(|d| d.to_string_lossy().to_string()),
+                    sub_category: info.sub_category,
+                    is_mature: info.is_mature,
+                    players: info.players,
+                    controls: info.controls,
+                    rating: info.rating,
+                    history: info.history,
+                    mameinfo: info.mameinfo,
+                    rotate: machine.rotate,
+                    screen_width: machine.screen_width,
+                    screen_height: machine.screen_height,
+                    refresh: machine.refresh,
+                    num_players: machine.players,
+                    num_buttons: machine.buttons,
+                    sound_channels: machine.sound_channels
                 });
-            } else {
-                println!("Title for rom {rom} not found");
             }
         }
     }
 
-    let mut roms = vec![];
+    for rom_name in rom_files.keys().filter(|n| !found.contains(*n)) {
+        println!("Rom meta data not found {rom_name}");
+    }
+
+    update_library(&mut roms, emulator)?;
+
+    // mame looks for parent, bios and device sets in every rom dir
+    RomLibrary::open()?.set_rom_path(emulator, &rom_path(&rom_files))
+}
 
+/// Name of the set a rom is launched from, the rom's own set when found.
+/// Merged sets hold the clone roms in the parent set, so clones missing from
+/// the rom dirs are launched from the parent set when `merged` is set.
+fn rom_set_name<'a>(
+    rom: &'a str,
+    clone_of: Option<&'a str>,
+    rom_files: &HashMap<String, PathBuf>,
+    merged: bool
+) -> Option<&'a str> {
+    if rom_files.contains_key(rom) {
+        Some(rom)
+    } else {
+        clone_of.filter(|parent| merged && rom_files.contains_key(*parent))
+    }
+}
+
+/// Mame rom path of every dir holding rom sets, separated by `;`
+fn rom_path(rom_files: &HashMap<String, PathBuf>) -> String {
+    rom_files.values()
+        .filter_map(|f| f.parent())
+        .map(|d| d.to_string_lossy())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// Find mame rom sets in `roms_dir` by set name. Sets are `.zip` or `.7z`
+/// archives, or directories holding the `.chd` files of disk based games.
+/// The archive is used when a set has both. Other directories are searched
+/// when `recursive` is set.
+fn find_rom_sets(
+    roms_dir: &Path,
+    recursive: bool,
+    rom_files: &mut HashMap<String, PathBuf>
+) -> Result<()> {
     for dir_entry in fs::read_dir(roms_dir)? {
         let dir_entry = dir_entry?.path();
 
         let rom_file_name = dir_entry.file_name()
             .ok_or(Error::msg("Rom dir entry must be file"))?
-            .to_string_lossy();
+            .to_string_lossy()
+            .to_string();
 
+        if dir_entry.is_dir() {
+            if has_chd_files(&dir_entry)? {
+                rom_files.entry(rom_file_name)
+                    .or_insert(dir_entry);
+            } else if recursive {
+                find_rom_sets(&dir_entry, recursive, rom_files)?;
+            } else {
+                println!("No chd files in {:?}", rom_file_name);
+            }
+            continue;
+        }
+
+        // mame set names are lower case, files on FAT or SMB shares may not be
         let rom_name = dir_entry
             .with_extension("")
             .file_name()
+            .ok_or(Error::msg("Rom dir entry must be file"))?
+            .to_string_lossy()
+            .to_lowercase();
+
+        if is_rom_archive(&dir_entry) {
+            match rom_files.get(&rom_name) {
+                Some(existing) if !existing.is_dir() => {
+                    println!("Rom set {rom_name} already found in {:?}", existing);
+                },
+                _ => {
+                    rom_files.insert(rom_name, dir_entry);
+                }
+            }
+        } else {
+            println!("Not a rom set {:?}", rom_file_name);
+        }
+    }
+
+    Ok(())
+}
+
+fn is_rom_archive(file: &Path) -> bool {
+    file.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip") || ext.eq_ignore_ascii_case("7z"))
+}
+
+fn has_chd_files(dir: &Path) -> Result<bool> {
+    for dir_entry in fs::read_dir(dir)? {
+        let is_chd = dir_entry?.path()
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("chd"));
+
+        if is_chd {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Scan directory for files matching `extensions` and add them to the library
+/// as roms of `system`. Titles are taken from the optional DAT file, matching
+/// files by name or CRC, and otherwise derived from the file names.
+pub fn scan_dir(
+    roms_dir: &Path,
+    system: &String,
+    extensions: &[String],
+    dat_file: Option<&Path>
+) -> Result<()> {
+    let dat = dat_file
+        .map(DatIndex::load_xml)
+        .transpose()?;
+
+    let mut roms = vec![];
+
+    for dir_entry in fs::read_dir(roms_dir)? {
+        let dir_entry = dir_entry?.path();
+
+        let matches_ext = dir_entry.extension()
+            .map(|ext| {
+                let ext = ext.to_string_lossy();
+                extensions.iter()
+                    .any(|e| e.trim_start_matches('.').eq_ignore_ascii_case(&ext))
+            })
+            .unwrap_or_default();
+
+        if !matches_ext || !dir_entry.is_file() {
+            println!("Skipping {:?}", dir_entry);
+            continue;
+        }
+
+        let rom_name = dir_entry.file_stem()
             .ok_or(Error::msg("Rom dir entry must be file"))?
             .to_string_lossy()
             .to_string();
 
-        if rom_file_name.ends_with(".zip") {
-            if let Some(rom) = rom_meta.get(&rom_name) {
-                roms.push(rom);
+        let mut rom = Rom {
+            title: clean_title(&rom_name),
+            name: rom_name,
+            category: system.clone(),
+            clone_of: None,
+            has_clones: false,
+            is_favourite: false,
+            year: None,
+            manufacturer: None,
+            emulator: Some(system.clone()),
+            path: Some(dir_entry.to_string_lossy().to_string()),
+            rom_dir: Some(roms_dir.to_string_lossy().to_string()),
+            sub_category: None,
+            is_mature: false,
+            players: None,
+            controls: None,
+            rating: None,
+            history: None,
+            mameinfo: None,
+            rotate: None,
+            screen_width: None,
+            screen_height: None,
+            refresh: None,
+            num_players: None,
+            num_buttons: None,
+            sound_channels: None
+        };
+
+        if let Some(dat) = &dat {
+            let file_name = dir_entry.file_name()
+                .unwrap_or_default()
+                .to_string_lossy();
+
+            // only checksum the file when the name isn't known to the DAT
+            let game = match dat.find_by_name(&file_name, &rom.name) {
+                Some(game) => Some(game),
+                None => dat.find_by_crc(file_crc32(&dir_entry)?)
+            };
+
+            if let Some(game) = game {
+                rom.title = clean_title(&game.description);
+                rom.clone_of = game.clone_of.clone();
+                rom.year = game.year.clone();
+                rom.manufacturer = game.manufacturer.clone();
             } else {
-                println!("Rom meta data not found {rom_name}");
+                println!("Rom {} not found in DAT file", file_name);
             }
-        } else {
-            println!("Not a zip file {:?}", rom_file_name);
         }
+
+        roms.push(rom);
     }
 
+    update_library(&mut roms, Some(system))
+}
+
+fn update_library(roms: &mut [Rom], emulator: Option<&String>) -> Result<()> {
+    promote_orphans(roms);
+
     let rom_lib = RomLibrary::open()?;
-    rom_lib.clear()?;
-    rom_lib.add_roms(&roms)?;
+    let summary = rom_lib.update_roms(roms, emulator)?;
 
-    println!("Added {} roms to library", roms.len());
+    println!(
+        "Scanned {} roms: {} added, {} updated, {} missing, {} skipped",
+        roms.len(), summary.added, summary.updated, summary.missing, summary.skipped
+    );
 
     Ok(())
 }
 
+/// List clones as top level roms when their parent wasn't found by the scan
+fn promote_orphans(roms: &mut [Rom]) {
+    let names: HashSet<String> = roms.iter()
+        .map(|r| r.name.clone())
+        .collect();
+
+    for rom in roms {
+        if rom.clone_of.as_ref().is_some_and(|p| !names.contains(p)) {
+            rom.clone_of = None;
+        }
+    }
+}
+
+/// Make title from No-Intro style file name by removing `(...)` and `[...]`
+/// tags and moving trailing articles to the front, e.g.
+/// "Legend of Zelda, The - A Link to the Past (USA) [!]" becomes
+/// "The Legend of Zelda - A Link to the Past". Names made only of tags are
+/// kept as is.
+fn clean_title(file_name: &str) -> String {
+    let mut title = String::with_capacity(file_name.len());
+    let mut depth = 0;
+
+    for c in file_name.chars() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' if depth > 0 => depth -= 1,
+            _ if depth == 0 => title.push(c),
+            _ => ()
+        }
+    }
+
+    let title = title.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    if title.is_empty() {
+        return file_name.trim().to_string();
+    }
+
+    let (name, subtitle) = match title.split_once(" - ") {
+        Some((name, subtitle)) => (name, Some(subtitle)),
+        None => (title.as_str(), None)
+    };
+
+    let name = ["The", "A", "An"].iter()
+        .find_map(|article| {
+            name.strip_suffix(&format!(", {}", article))
+                .map(|n| format!("{} {}", article, n))
+        })
+        .unwrap_or(name.to_string());
+
+    match subtitle {
+        Some(subtitle) => format!("{} - {}", name, subtitle),
+        None => name
+    }
+}
+
+fn file_crc32(file_path: &Path) -> Result<u32> {
+    let mut file = File::open(file_path)?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buf = [0; 64 * 1024];
+
+    loop {
+        match file.read(&mut buf)? {
+            0 => break,
+            len => hasher.update(&buf[..len])
+        }
+    }
+
+    Ok(hasher.finalize())
+}
+
 fn parse_genre_ini(genre_ini: &Path) -> Result<Ini> {
     // new_cs() to preserve case of category names
     let mut genre = Ini::new_cs();
@@ -96,3 +436,81 @@ fn parse_genre_ini(genre_ini: &Path) -> Result<Ini> {
 
     Ok(genre)
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom_files(names: &[&str]) -> HashMap<String, PathBuf> {
+        names.iter()
+            .map(|name| (name.to_string(), PathBuf::from(format!("roms/{}.zip", name))))
+            .collect()
+    }
+
+    #[test]
+    fn skips_missing_clone_of_split_set() {
+        let rom_files = rom_files(&["sf2"]);
+
+        assert_eq!(rom_set_name("sf2", None, &rom_files, false), Some("sf2"));
+        assert_eq!(rom_set_name("sf2ua", Some("sf2"), &rom_files, false), None);
+    }
+
+    #[test]
+    fn launches_missing_clone_of_merged_set_from_parent() {
+        let rom_files = rom_files(&["sf2", "sf2ce"]);
+
+        assert_eq!(rom_set_name("sf2ua", Some("sf2"), &rom_files, true), Some("sf2"));
+        assert_eq!(rom_set_name("sf2ce", Some("sf2"), &rom_files, true), Some("sf2ce"));
+        assert_eq!(rom_set_name("sf2ua", Some("sf2j"), &rom_files, true), None);
+    }
+
+    #[test]
+    fn finds_rom_archives_in_any_case() {
+        assert!(is_rom_archive(Path::new("roms/sf2.zip")));
+        assert!(is_rom_archive(Path::new("roms/SF2.ZIP")));
+        assert!(is_rom_archive(Path::new("roms/kof98.7Z")));
+        assert!(!is_rom_archive(Path::new("roms/sf2.txt")));
+        assert!(!is_rom_archive(Path::new("roms/zip")));
+    }
+
+    #[test]
+    fn joins_dirs_of_rom_sets_into_rom_path() {
+        let rom_files = HashMap::from([
+            ("sf2".to_string(), PathBuf::from("roms/capcom/sf2.zip")),
+            ("qsound".to_string(), PathBuf::from("roms/bios/qsound.zip")),
+            ("sfa".to_string(), PathBuf::from("roms/capcom/sfa.zip")),
+        ]);
+
+        assert_eq!(rom_path(&rom_files), "roms/bios;roms/capcom");
+    }
+
+
+    #[test]
+    fn removes_region_and_revision_tags() {
+        assert_eq!(clean_title("Super Metroid (Japan, USA) (En,Ja)"), "Super Metroid");
+        assert_eq!(clean_title("Street Fighter II (USA) (Rev 1) [!]"), "Street Fighter II");
+        assert_eq!(clean_title("Tetris [T+Fre] (Europe)"), "Tetris");
+    }
+
+    #[test]
+    fn moves_trailing_article_to_front() {
+        assert_eq!(
+            clean_title("Legend of Zelda, The - A Link to the Past (USA)"),
+            "The Legend of Zelda - A Link to the Past"
+        );
+        assert_eq!(clean_title("Boy and His Blob, A (USA)"), "A Boy and His Blob");
+    }
+
+    #[test]
+    fn removes_nested_brackets() {
+        assert_eq!(clean_title("Sonic (Beta [Prototype (1991)]) (USA)"), "Sonic");
+        assert_eq!(clean_title("Contra (USA) ] (Alt)"), "Contra ]");
+    }
+
+    #[test]
+    fn keeps_name_made_only_of_tags() {
+        assert_eq!(clean_title("[BIOS] (Japan)"), "[BIOS] (Japan)");
+        assert_eq!(clean_title(""), "");
+    }
+}
\ No newline at end of file
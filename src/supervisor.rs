@@ -0,0 +1,271 @@
+/*
+ * Lemon Launcher - SDL based MAME frontend for arcade cabinets
+ * Copyright (C) 2024 Josh Kropf <josh@slashdev.ca>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{
+    os::unix::process::CommandExt,
+    process::{Child, Command, ExitStatus},
+    thread,
+    time::{Duration, Instant}
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    input::InputEvent,
+    keymap::{Action, InputDispatcher},
+    lemon_config::{ExecCommand, SupervisorConfig},
+    template::TemplateVars,
+    MainLoopContext
+};
+
+/// Milliseconds between checks of a running process
+const POLL_INTERVAL: u64 = 50;
+
+/// Wait for a process to exit, stopping it when the `kill_emulator` input is
+/// pressed or after `timeout` seconds
+///
+/// Keyboard inputs go to the emulator, only controller and joystick inputs
+/// are seen while it runs.
+pub fn supervise(
+    mut child: Child,
+    ctx: &mut MainLoopContext,
+    keymap: &mut InputDispatcher,
+    config: &SupervisorConfig,
+    timeout: Option<u64>
+) -> Result<ExitStatus> {
+    let started_at = Instant::now();
+    let timeout = timeout.map(Duration::from_secs);
+    let has_terminal = is_foreground(child.id() as libc::pid_t);
+
+    // the input that launched the process may still be held
+    keymap.release_all();
+
+    loop {
+        if let Some(status) = child.try_wait()? {
+            keymap.release_all();
+            reclaim_terminal(has_terminal);
+            return Ok(status);
+        }
+
+        if kill_requested(ctx, keymap) {
+            println!("Stopping process {}", child.id());
+            break;
+        }
+
+        if timeout.is_some_and(|t| started_at.elapsed() >= t) {
+            println!("Process {} timed out, stopping it", child.id());
+            break;
+        }
+
+        thread::sleep(Duration::from_millis(POLL_INTERVAL));
+    }
+
+    let status = terminate(child, config.kill_wait);
+    keymap.release_all();
+    reclaim_terminal(has_terminal);
+
+    status
+}
+
+/// Make the process group of `cmd` the foreground group of the terminal when
+/// the launcher has it, so processes that change terminal settings or read
+/// stdin aren't stopped by SIGTTOU or SIGTTIN. `supervise` gives the terminal
+/// back when the process exits.
+pub fn give_terminal(cmd: &mut Command) {
+    if !is_foreground(unsafe { libc::getpgrp() }) {
+        return;
+    }
+
+    // runs in the child after it's put in its own process group, and only
+    // makes async-signal-safe calls
+    unsafe {
+        cmd.pre_exec(|| {
+            set_foreground(libc::getpgrp());
+            Ok(())
+        });
+    }
+}
+
+fn reclaim_terminal(has_terminal: bool) {
+    if has_terminal {
+        set_foreground(unsafe { libc::getpgrp() });
+    }
+}
+
+/// Check if `pgid` is the foreground process group of the terminal on stdin
+fn is_foreground(pgid: libc::pid_t) -> bool {
+    unsafe {
+        libc::isatty(libc::STDIN_FILENO) == 1 && libc::tcgetpgrp(libc::STDIN_FILENO) == pgid
+    }
+}
+
+/// Make `pgid` the foreground process group of the terminal on stdin, which a
+/// background process can only do while ignoring SIGTTOU
+fn set_foreground(pgid: libc::pid_t) {
+    unsafe {
+        let handler = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+        libc::tcsetpgrp(libc::STDIN_FILENO, pgid);
+        libc::signal(libc::SIGTTOU, handler);
+    }
+}
+
+/// Run launch hooks in turn, stopping at the first hook that can't be run or
+/// exits with an error, which is returned as the error
+pub fn run_hooks(
+    hooks: &[ExecCommand],
+    vars: &TemplateVars,
+    ctx: &mut MainLoopContext,
+    keymap: &mut InputDispatcher,
+    config: &SupervisorConfig
+) -> Result<()> {
+    for hook in hooks {
+        let child = hook.spawn_hook(vars)
+            .map_err(|e| anyhow!("Unable to run {}: {}", hook.cmd, e))?;
+
+        let status = supervise(child, ctx, keymap, config, config.hook_timeout)?;
+        if !status.success() {
+            return Err(anyhow!("{} failed, {}", hook.cmd, status));
+        }
+    }
+
+    Ok(())
+}
+
+fn kill_requested(ctx: &mut MainLoopContext, keymap: &mut InputDispatcher) -> bool {
+    let mut actions = vec![];
+
+    for event in ctx.event_pump.poll_iter() {
+        ctx.inputs.update_devices(&event);
+
+        for input_event in ctx.inputs.translate(&event) {
+            let action = match input_event {
+                InputEvent::Down { input, repeat } => keymap.press(input, repeat),
+                InputEvent::Up(input) => keymap.release(input)
+            };
+            actions.extend(action);
+        }
+    }
+
+    actions.extend(keymap.poll());
+
+    actions.contains(&Action::KillEmulator)
+}
+
+/// Ask the process group of the child to exit with SIGTERM so it can save its
+/// state, then SIGKILL it when the child hasn't exited after `kill_wait`
+/// milliseconds
+fn terminate(mut child: Child, kill_wait: u32) -> Result<ExitStatus> {
+    // children are spawned as process group leaders, and the group id stays
+    // valid until the child is reaped by try_wait or wait
+    let pgid = child.id() as libc::pid_t;
+
+    unsafe {
+        libc::kill(-pgid, libc::SIGTERM);
+    }
+
+    let deadline = Instant::now() + Duration::from_millis(kill_wait as u64);
+
+    while Instant::now() < deadline {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+
+        thread::sleep(Duration::from_millis(POLL_INTERVAL));
+    }
+
+    println!("Process {} didn't exit, killing it", child.id());
+
+    unsafe {
+        libc::kill(-pgid, libc::SIGKILL);
+    }
+
+    Ok(child.wait()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, os::fd::FromRawFd, process::Stdio, ptr};
+
+    use super::*;
+
+    /// Set by `gives_terminal_to_child` to run `runs_child_on_terminal` in a
+    /// process that has a terminal
+    const TERMINAL_TEST_VAR: &str = "LEMON_TERMINAL_TEST";
+
+    #[test]
+    fn gives_terminal_to_child() {
+        let (mut master, mut slave) = (0, 0);
+        let opened = unsafe {
+            libc::openpty(&mut master, &mut slave, ptr::null_mut(), ptr::null(), ptr::null())
+        };
+        assert_eq!(opened, 0);
+
+        let mut cmd = Command::new(env::current_exe().unwrap());
+        cmd.args(["--exact", "supervisor::tests::runs_child_on_terminal", "--test-threads=1"])
+            .env(TERMINAL_TEST_VAR, "1")
+            .stdin(unsafe { Stdio::from_raw_fd(slave) })
+            .stdout(Stdio::null());
+
+        // new session with the pty as its controlling terminal
+        unsafe {
+            cmd.pre_exec(|| {
+                libc::setsid();
+                libc::ioctl(libc::STDIN_FILENO, libc::TIOCSCTTY, 0);
+                Ok(())
+            });
+        }
+
+        let status = cmd.status().unwrap();
+        unsafe { libc::close(master) };
+
+        assert!(status.success());
+    }
+
+    #[test]
+    fn runs_child_on_terminal() {
+        if env::var(TERMINAL_TEST_VAR).is_err() {
+            return;
+        }
+
+        assert!(is_foreground(unsafe { libc::getpgrp() }));
+
+        // stty changes the terminal settings with tcsetattr
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "stty -echo && stty echo"])
+            .process_group(0);
+        give_terminal(&mut cmd);
+
+        let mut child = cmd.spawn().unwrap();
+        let has_terminal = is_foreground(child.id() as libc::pid_t);
+        let deadline = Instant::now() + Duration::from_secs(5);
+
+        // a child stopped by SIGTTOU never exits
+        let status = loop {
+            if let Some(status) = child.try_wait().unwrap() {
+                break status;
+            }
+            assert!(Instant::now() < deadline, "child stopped");
+            thread::sleep(Duration::from_millis(POLL_INTERVAL));
+        };
+        reclaim_terminal(has_terminal);
+
+        assert!(has_terminal);
+        assert!(status.success());
+        assert!(is_foreground(unsafe { libc::getpgrp() }));
+    }
+}
\ No newline at end of file
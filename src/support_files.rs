@@ -0,0 +1,309 @@
+/*
+ * Lemon Launcher - SDL based MAME frontend for arcade cabinets
+ * Copyright (C) 2024 Josh Kropf <josh@slashdev.ca>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{collections::HashMap, fs::{self, File}, io::BufReader, path::{Path, PathBuf}};
+
+use anyhow::{Error, Result};
+use clap::Args;
+use configparser::ini::Ini;
+use quick_xml::{events::Event, Reader};
+
+/// Optional support files with extra rom metadata
+#[derive(Args)]
+pub struct SupportFiles {
+    /// catver.ini with sub-categories and mature flag
+    #[arg(long)]
+    pub catver: Option<PathBuf>,
+    /// nplayers.ini with player counts
+    #[arg(long)]
+    pub nplayers: Option<PathBuf>,
+    /// controls.ini or controls.xml with control types
+    #[arg(long)]
+    pub controls: Option<PathBuf>,
+    /// bestgames.ini with ratings
+    #[arg(long)]
+    pub bestgames: Option<PathBuf>,
+    /// history.dat with game history text
+    #[arg(long)]
+    pub history: Option<PathBuf>,
+    /// mameinfo.dat with emulation notes
+    #[arg(long)]
+    pub mameinfo: Option<PathBuf>
+}
+
+#[derive(Default)]
+pub struct RomInfo {
+    pub sub_category: Option<String>,
+    pub is_mature: bool,
+    pub players: Option<String>,
+    pub controls: Option<String>,
+    pub rating: Option<String>,
+    pub history: Option<String>,
+    pub mameinfo: Option<String>
+}
+
+impl SupportFiles {
+    /// Load metadata of all support files by rom name
+    pub fn load(&self) -> Result<HashMap<String, RomInfo>> {
+        let mut info: HashMap<String, RomInfo> = HashMap::new();
+
+        if let Some(catver) = &self.catver {
+            for (rom, category) in section_values(&load_ini(catver)?, "Category") {
+                // mature games are tagged e.g. "Shooter / Flying Vertical * Mature *"
+                let is_mature = category.contains("* Mature *");
+                let category = category.replace("* Mature *", "");
+
+                let rom_info = info.entry(rom).or_default();
+                rom_info.sub_category = Some(category.trim().to_string());
+                rom_info.is_mature = is_mature;
+            }
+        }
+
+        if let Some(nplayers) = &self.nplayers {
+            for (rom, players) in section_values(&load_ini(nplayers)?, "NPlayers") {
+                info.entry(rom).or_default().players = Some(players);
+            }
+        }
+
+        if let Some(controls) = &self.controls {
+            let is_xml = controls.extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("xml"));
+
+            let controls = if is_xml {
+                parse_controls_xml(controls)?
+            } else {
+                parse_controls_ini(controls)?
+            };
+
+            for (rom, controls) in controls {
+                info.entry(rom).or_default().controls = Some(controls);
+            }
+        }
+
+        if let Some(bestgames) = &self.bestgames {
+            let bestgames = load_ini(bestgames)?;
+
+            // sections are ratings, e.g. "[90 to 100 (Best)]", with roms as keys
+            let ratings = bestgames.get_map_ref()
+                .iter()
+                .filter(|(section, _)| !["FOLDER_SETTINGS", "ROOT_FOLDER"].contains(&section.as_str()));
+
+            for (rating, roms) in ratings {
+                for rom in roms.keys() {
+                    info.entry(rom.clone()).or_default().rating = Some(rating.clone());
+                }
+            }
+        }
+
+        if let Some(history) = &self.history {
+            for (rom, text) in parse_dat(history, "$bio")? {
+                info.entry(rom).or_default().history = Some(text);
+            }
+        }
+
+        if let Some(mameinfo) = &self.mameinfo {
+            for (rom, text) in parse_dat(mameinfo, "$mame")? {
+                info.entry(rom).or_default().mameinfo = Some(text);
+            }
+        }
+
+        Ok(info)
+    }
+}
+
+fn load_ini(file_path: &Path) -> Result<Ini> {
+    // new_cs() to preserve case of values and section names
+    let mut ini = Ini::new_cs();
+
+    ini.load(file_path)
+        .map_err(Error::msg)?;
+
+    Ok(ini)
+}
+
+fn section_values(ini: &Ini, section: &str) -> Vec<(String, String)> {
+    ini.get_map_ref()
+        .get(section)
+        .into_iter()
+        .flatten()
+        .filter_map(|(key, val)| val.as_ref().map(|v| (key.clone(), v.clone())))
+        .collect()
+}
+
+/// Text entries of history.dat or mameinfo.dat, e.g.
+/// `$info=sf2,sf2ua,` followed by `section` (e.g. `$bio`), the text and `$end`
+fn parse_dat(file_path: &Path, section: &str) -> Result<Vec<(String, String)>> {
+    let src = fs::read(file_path)?;
+    let src = String::from_utf8_lossy(&src);
+
+    let mut result = vec![];
+    let mut roms: Vec<&str> = vec![];
+    let mut text: Option<Vec<&str>> = None;
+
+    for line in src.lines() {
+        let line = line.trim_end();
+
+        if let Some(lines) = &mut text {
+            if line == "$end" {
+                let entry = lines.join("\n").trim().to_string();
+                for rom in roms.drain(..) {
+                    result.push((rom.to_string(), entry.clone()));
+                }
+                text = None;
+            } else {
+                lines.push(line);
+            }
+        } else if let Some(info) = line.strip_prefix("$info=") {
+            roms = info.split(',')
+                .map(|r| r.trim())
+                .filter(|r| !r.is_empty())
+                .collect();
+        } else if line == section {
+            text = Some(vec![]);
+        }
+    }
+
+    Ok(result)
+}
+
+/// Controls of player one from controls.ini, where each rom is a section with
+/// e.g. `P1Controls=8-way Joystick+joy8way|Just Buttons+P1Buttons`
+fn parse_controls_ini(file_path: &Path) -> Result<Vec<(String, String)>> {
+    let ini = load_ini(file_path)?;
+
+    let controls = ini.get_map_ref()
+        .iter()
+        .filter_map(|(rom, values)| {
+            let controls = values.get("P1Controls")?.as_ref()?;
+            let controls = controls.split('|')
+                .map(|c| c.split('+').next().unwrap_or_default().trim())
+                .filter(|c| !c.is_empty())
+                .collect::<Vec<_>>()
+                .join(", ");
+            Some((rom.clone(), controls))
+        })
+        .collect();
+
+    Ok(controls)
+}
+
+/// Controls of player one from controls.xml, e.g.
+/// `<game romname="sf2"><player number="1"><controls name="8-way Joystick">`
+fn parse_controls_xml(file_path: &Path) -> Result<Vec<(String, String)>> {
+    let mut reader = Reader::from_reader(BufReader::new(File::open(file_path)?));
+    let mut buf = Vec::new();
+
+    let mut result = vec![];
+    let mut rom: Option<String> = None;
+    let mut player: Option<String> = None;
+    let mut controls: Vec<String> = vec![];
+
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Eof => break,
+            Event::Start(e) | Event::Empty(e) => {
+                let attr = |key: &[u8]| -> Result<Option<String>> {
+                    match e.try_get_attribute(key)? {
+                        Some(a) => Ok(Some(a.decode_and_unescape_value(reader.decoder())?.into_owned())),
+                        None => Ok(None)
+                    }
+                };
+
+                match e.name().as_ref() {
+                    b"game" => rom = attr(b"romname")?,
+                    b"player" => player = attr(b"number")?,
+                    b"controls" if player.as_deref() == Some("1") => {
+                        controls.extend(attr(b"name")?);
+                    },
+                    _ => ()
+                }
+            },
+            Event::End(e) if e.name().as_ref() == b"game" => {
+                if let Some(rom) = rom.take() {
+                    if !controls.is_empty() {
+                        result.push((rom, controls.join(", ")));
+                    }
+                }
+                player = None;
+                controls.clear();
+            },
+            _ => ()
+        }
+
+        buf.clear();
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> Option<PathBuf> {
+        Some(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name))
+    }
+
+    #[test]
+    fn loads_all_support_files() {
+        let files = SupportFiles {
+            catver: fixture("catver.ini"),
+            nplayers: fixture("nplayers.ini"),
+            controls: fixture("controls.ini"),
+            bestgames: fixture("bestgames.ini"),
+            history: fixture("history.dat"),
+            mameinfo: fixture("mameinfo.dat")
+        };
+
+        let info = files.load().unwrap();
+
+        let sf2 = &info["sf2"];
+        assert_eq!(sf2.sub_category.as_deref(), Some("Fighter / Versus"));
+        assert!(!sf2.is_mature);
+        assert_eq!(sf2.players.as_deref(), Some("2P sim"));
+        assert_eq!(sf2.controls.as_deref(), Some("8-way Joystick"));
+        assert_eq!(sf2.rating.as_deref(), Some("90 to 100 (Best)"));
+
+        let dkong = &info["dkong"];
+        assert_eq!(dkong.controls.as_deref(), Some("4-way Joystick, Just Buttons"));
+
+        let mature = &info["3bagflnz"];
+        assert_eq!(mature.sub_category.as_deref(), Some("Casino / Reels"));
+        assert!(mature.is_mature);
+
+        assert!(!info.contains_key("RootFolderIcon"));
+
+        let history = info["sf2ua"].history.as_deref().unwrap();
+        assert!(history.starts_with("Street Fighter II (c) 1991 Capcom."));
+        assert!(history.ends_with("- TRIVIA -\n\nReleased in March 1991."));
+        assert_eq!(info["sf2"].history.as_deref(), Some(history));
+
+        assert_eq!(info["dkong"].mameinfo.as_deref(), Some("Sound is sampled."));
+        assert_eq!(info["dkong"].history, None);
+    }
+
+    #[test]
+    fn loads_controls_xml() {
+        let controls = parse_controls_xml(&fixture("controls.xml").unwrap()).unwrap();
+
+        assert_eq!(controls, vec![
+            ("sf2".to_string(), "8-way Joystick".to_string()),
+            ("dkong".to_string(), "4-way Joystick".to_string())
+        ]);
+    }
+}
@@ -0,0 +1,133 @@
+/*
+ * Lemon Launcher - SDL based MAME frontend for arcade cabinets
+ * Copyright (C) 2024 Josh Kropf <josh@slashdev.ca>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{collections::HashMap, env, path::Path};
+
+use crate::env::Env;
+
+/// Values for placeholders in exec command `args` and `env`.
+///
+/// `{name}` is replaced with the value of var `name`, placeholders of unknown
+/// vars are left as is. `${NAME}` is replaced with the value of environment
+/// variable `NAME`, or an empty string when it is not set.
+pub struct TemplateVars {
+    vars: HashMap<&'static str, String>
+}
+
+impl TemplateVars {
+    pub fn new(env: &Env) -> Self {
+        let mut vars = TemplateVars { vars: HashMap::new() };
+        vars.set_path("config_dir", &env.config_dir);
+        vars.set_path("state_dir", &env.state_dir);
+        vars
+    }
+
+    pub fn set(&mut self, name: &'static str, value: impl Into<String>) {
+        self.vars.insert(name, value.into());
+    }
+
+    pub fn set_path(&mut self, name: &'static str, value: &Path) {
+        self.set(name, value.to_string_lossy());
+    }
+
+    /// Vars as environment variables, e.g. `rom` as `LEMON_ROM`
+    pub fn env_vars(&self) -> impl Iterator<Item = (String, &String)> {
+        self.vars.iter()
+            .map(|(name, value)| (format!("LEMON_{}", name.to_uppercase()), value))
+    }
+
+    pub fn apply(&self, src: &str) -> String {
+        let mut result = String::with_capacity(src.len());
+        let mut rest = src;
+
+        while let Some(start) = rest.find('{') {
+            let is_env = rest[..start].ends_with('$');
+            let Some(len) = rest[start..].find('}') else {
+                break
+            };
+
+            let name = &rest[start + 1..start + len];
+            let prefix = &rest[..start];
+
+            if is_env {
+                result.push_str(&prefix[..prefix.len() - 1]);
+                result.push_str(&env::var(name).unwrap_or_default());
+            } else if let Some(value) = self.vars.get(name) {
+                result.push_str(prefix);
+                result.push_str(value);
+            } else {
+                result.push_str(&rest[..=start + len]);
+            }
+
+            rest = &rest[start + len + 1..];
+        }
+
+        result.push_str(rest);
+        result
+    }
+}
+
+/// Check if template string contains placeholder for var `name`
+pub fn has_var(src: &str, name: &str) -> bool {
+    src.contains(&format!("{{{}}}", name))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> TemplateVars {
+        let mut vars = TemplateVars { vars: HashMap::new() };
+        vars.set("rom", "sf2");
+        vars.set("rom_dir", "/roms");
+        vars
+    }
+
+    #[test]
+    fn replaces_vars() {
+        assert_eq!(vars().apply("{rom_dir}/{rom}.zip"), "/roms/sf2.zip");
+        assert_eq!(vars().apply("-rompath"), "-rompath");
+    }
+
+    #[test]
+    fn replaces_env_vars() {
+        env::set_var("LEMON_TEMPLATE_TEST", "/home/arcade");
+
+        assert_eq!(vars().apply("${LEMON_TEMPLATE_TEST}/cfg/{rom}"), "/home/arcade/cfg/sf2");
+        assert_eq!(vars().apply("${LEMON_TEMPLATE_UNSET}/cfg"), "/cfg");
+    }
+
+    #[test]
+    fn keeps_unknown_vars() {
+        assert_eq!(vars().apply("{title} {rom}"), "{title} sf2");
+    }
+
+    #[test]
+    fn keeps_unterminated_braces() {
+        assert_eq!(vars().apply("{rom} {rom"), "sf2 {rom");
+        assert_eq!(vars().apply("${HOME"), "${HOME");
+    }
+
+    #[test]
+    fn finds_vars() {
+        assert!(has_var("{rom_dir}/{rom}.zip", "rom"));
+        assert!(!has_var("{rom_dir}", "rom"));
+        assert!(!has_var("{rom", "rom"));
+    }
+}
\ No newline at end of file